@@ -0,0 +1,58 @@
+//! Compare drawing pieces from the raster cache with rendering their SVGs
+//! on every frame, like during a drag at 60 frames per second.
+//!
+//! Run with `cargo bench --bench piece_cache`.
+
+extern crate cairo;
+extern crate chessground;
+extern crate rsvg;
+extern crate shakmaty;
+
+use std::time::Instant;
+
+use cairo::{Context, Format, ImageSurface};
+use rsvg::HandleExt;
+use shakmaty::{Color, Role, Piece};
+
+use chessground::PieceSet;
+
+const SQUARE_PX: i32 = 64;
+const FRAMES: u32 = 600;
+
+const ROLES: [Role; 6] = [Role::Pawn, Role::Knight, Role::Bishop, Role::Rook, Role::Queen, Role::King];
+
+fn bench<F: Fn(&Context, &Piece)>(name: &str, draw: F) {
+    let surface = ImageSurface::create(Format::ARgb32, 8 * SQUARE_PX, 8 * SQUARE_PX).expect("surface");
+    let cr = Context::new(&surface).expect("context");
+    cr.scale(f64::from(SQUARE_PX), f64::from(SQUARE_PX));
+
+    let pieces: Vec<Piece> = ROLES.iter().map(|role| role.of(Color::White))
+        .chain(ROLES.iter().map(|role| role.of(Color::Black)))
+        .collect();
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        for (i, piece) in pieces.iter().enumerate() {
+            cr.save().expect("save");
+            cr.translate((i % 8) as f64, (i / 8) as f64);
+            draw(&cr, piece);
+            cr.restore().expect("restore");
+        }
+    }
+
+    println!("{}: {:?} per frame of {} pieces", name, start.elapsed() / FRAMES, pieces.len());
+}
+
+fn main() {
+    let piece_set = PieceSet::merida();
+
+    bench("svg", |cr, piece| {
+        let scale = piece_set.scale_of(piece);
+        cr.scale(scale, scale);
+        piece_set.by_piece(piece).render_cairo(cr);
+    });
+
+    bench("cached", |cr, piece| {
+        piece_set.render(cr, piece, 1.0).expect("render");
+    });
+}
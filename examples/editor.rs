@@ -10,7 +10,7 @@ use relm::Widget;
 use relm_derive::widget;
 
 use shakmaty::{Square, Board};
-use chessground::{Ground, UserMove, SetBoard};
+use chessground::{Ground, GroundInit, UserMove, SetBoard};
 
 use self::Msg::*;
 
@@ -42,7 +42,7 @@ impl Widget for Win {
         gtk::Window {
             title: "Chessground",
             #[name="ground"]
-            Ground {
+            Ground(GroundInit::default()) {
                 UserMove(orig, dest, _) => PieceMoved(orig, dest),
             },
             delete_event(_, _) => (Quit, Inhibit(false)),
@@ -16,7 +16,7 @@ use relm::Widget;
 use relm_derive::widget;
 
 use shakmaty::{Square, Role, Move, Chess, Position};
-use chessground::{Ground, UserMove, SetPos, Pos, Flip};
+use chessground::{Ground, GroundInit, UserMove, SetPos, Pos, Flip};
 
 use self::Msg::*;
 
@@ -139,7 +139,7 @@ impl Widget for Win {
         gtk::Window {
             gtk::Box {
                 #[name="ground"]
-                Ground {
+                Ground(GroundInit::default()) {
                     UserMove(orig, dest, promotion) => MovePlayed(orig, dest, promotion),
                     scroll_event(_, e) => (Scroll(e.direction()), Inhibit(false)),
                 },
@@ -15,21 +15,169 @@
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
 use std::f64::consts::PI;
+use std::mem;
+use std::rc::Rc;
 
-use cairo::{Context, RadialGradient};
+use time::SteadyTime;
 
-use shakmaty::{Color, Square, Role, Bitboard, Chess, Position, Move, MoveList};
+use cairo::{Antialias, Context, FillRule, RadialGradient};
+use rsvg::{Handle, HandleExt};
+
+use shakmaty::{Color, Square, File, Rank, Role, Bitboard, Chess, Position, Move, MoveList};
+use shakmaty::san::San;
 
 use pieceset::PieceSet;
-use util::{file_to_float, rank_to_float};
+use theme::BoardTheme;
+use util::{ease, file_to_float, rank_to_float, sans};
+use ground::WidgetContext;
+
+/// How the selected square is highlighted.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SelectionStyle {
+    /// Fill the square.
+    Fill,
+    /// Stroke the border of the square, keeping the piece fully visible.
+    Outline,
+}
+
+/// How the promotion choices are arranged.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum PromotionLayout {
+    /// A single column, starting on the promotion square.
+    Column,
+    /// Two columns, starting on the promotion square and extending towards
+    /// the center, for small boards.
+    Grid,
+}
+
+/// Which highlight is drawn on a square that is both selected and part of
+/// the last move.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum HighlightOverlap {
+    /// Draw both highlights on top of each other.
+    Stack,
+    /// Draw only the selection.
+    SelectionWins,
+    /// Draw only the last move.
+    LastMoveWins,
+}
+
+/// Where coordinates are drawn.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CoordinateMode {
+    /// Next to every edge square.
+    Always,
+    /// Only next to empty edge squares, so that labels do not crowd
+    /// occupied back ranks.
+    EmptyEdges,
+    /// In the corners of the bottom rank and left file, like lichess,
+    /// leaving out the border around the board.
+    InsideSquares,
+    /// Not at all, leaving out the border around the board.
+    Hidden,
+}
+
+/// Rendering quality of squares, highlights and shapes.
+///
+/// Pieces are rendered from SVG and may still be antialiased.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum RenderQuality {
+    /// Antialiased edges.
+    Smooth,
+    /// Crisp pixel edges without antialiasing, e.g. for low resolution
+    /// displays.
+    Crisp,
+}
 
+/// Easing curve of piece animations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Easing {
+    /// Constant speed.
+    Linear,
+    /// Accelerate, then decelerate.
+    InOutCubic,
+}
+
+impl Easing {
+    /// Interpolate from `start` to `end` at progress `t` from 0.0 to 1.0.
+    pub fn ease(self, start: f64, end: f64, t: f64) -> f64 {
+        match self {
+            Easing::Linear => start + (end - start) * t.max(0.0).min(1.0),
+            Easing::InOutCubic => ease(start, end, t),
+        }
+    }
+}
+
+/// Paints a square instead of the plain theme color. Called with the
+/// square and whether it is light. The context is translated and clipped so
+/// that the square spans from (0, 0) to (1, 1).
+pub type SquarePainter = Box<dyn Fn(&Context, Square, bool)>;
+
+pub const HIGHLIGHT_FADE_MS: i64 = 150;
+/// Left edge and width of the eval bar, in board coordinates.
+pub const EVAL_BAR_LEFT: f64 = -0.95;
+pub const EVAL_BAR_WIDTH: f64 = 0.4;
+const PULSE_MS: i64 = 2000;
+const PULSES: f64 = 4.0;
+
+const RANK_GLYPHS: [&str; 8] = ["1", "2", "3", "4", "5", "6", "7", "8"];
+const FILE_GLYPHS: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+
+#[derive(Clone)]
 pub struct BoardState {
     orientation: Color,
     check: Option<Square>,
     last_move: Option<(Square, Square)>,
+    previous_last_move: Option<(Square, Square)>,
+    last_move_since: SteadyTime,
     turn: Option<Color>,
     piece_set: PieceSet,
     legals: MoveList,
+    sans: Vec<San>,
+    show_move_hints: bool,
+    board_svg: Option<Handle>,
+    show_check: bool,
+    mirror_files: bool,
+    selection_style: SelectionStyle,
+    animate_promotions: bool,
+    max_size: Option<i32>,
+    fixed_square_px: Option<i32>,
+    middle_click_deselects: bool,
+    theme: BoardTheme,
+    animate_highlights: bool,
+    spotlight: Bitboard,
+    switch_selection: bool,
+    premove: Option<(Square, Square)>,
+    allow_null_drop: bool,
+    drag_legal_only: bool,
+    drag_delay_ms: i64,
+    immovable: Bitboard,
+    hover_highlight: bool,
+    hovered: Option<Square>,
+    hover_piece_fade: Option<f64>,
+    drag_coordinate_tooltip: bool,
+    castling_rooks: Bitboard,
+    promotion_layout: PromotionLayout,
+    animate_shapes: bool,
+    highlight_overlap: HighlightOverlap,
+    square_painter: Option<Rc<SquarePainter>>,
+    select_on_drag: bool,
+    tilt: f64,
+    coordinate_mode: CoordinateMode,
+    render_quality: RenderQuality,
+    coordinate_orientation: Option<Color>,
+    show_eval_bar: bool,
+    eval: f64,
+    pulse: Option<(Square, SteadyTime)>,
+    piece_opacity: f64,
+    persist_hints_after_move: bool,
+    all_targets: Option<Color>,
+    confirm_moves: bool,
+    pending_move: Option<(Square, Square)>,
+    hover_events: bool,
+    view_only: bool,
+    premovable: bool,
+    cursor: Option<Square>,
 }
 
 impl BoardState {
@@ -42,9 +190,56 @@ impl BoardState {
             orientation: pos.turn(),
             check: None,
             last_move: None,
+            previous_last_move: None,
+            last_move_since: SteadyTime::now(),
             turn: None,
             piece_set: PieceSet::merida(),
             legals: MoveList::new(),
+            sans: Vec::new(),
+            show_move_hints: true,
+            board_svg: None,
+            show_check: true,
+            mirror_files: false,
+            selection_style: SelectionStyle::Fill,
+            animate_promotions: false,
+            max_size: None,
+            fixed_square_px: None,
+            middle_click_deselects: true,
+            theme: BoardTheme::default(),
+            animate_highlights: false,
+            spotlight: Bitboard(0),
+            switch_selection: true,
+            premove: None,
+            allow_null_drop: false,
+            drag_legal_only: false,
+            drag_delay_ms: 0,
+            immovable: Bitboard(0),
+            hover_highlight: false,
+            hovered: None,
+            hover_piece_fade: None,
+            drag_coordinate_tooltip: false,
+            castling_rooks: Bitboard(0),
+            promotion_layout: PromotionLayout::Column,
+            animate_shapes: false,
+            highlight_overlap: HighlightOverlap::Stack,
+            square_painter: None,
+            select_on_drag: true,
+            tilt: 0.0,
+            coordinate_mode: CoordinateMode::Always,
+            render_quality: RenderQuality::Smooth,
+            coordinate_orientation: None,
+            show_eval_bar: false,
+            eval: 0.0,
+            pulse: None,
+            piece_opacity: 1.0,
+            persist_hints_after_move: false,
+            all_targets: None,
+            confirm_moves: false,
+            pending_move: None,
+            hover_events: true,
+            view_only: false,
+            premovable: false,
+            cursor: None,
         };
 
         state.set_position(pos);
@@ -54,17 +249,32 @@ impl BoardState {
     pub fn set_position<P: Position>(&mut self, pos: &P) {
         self.check = if pos.checkers().any() { pos.board().king_of(pos.turn()) } else { None };
         self.legals = pos.legal_moves();
+        self.sans = sans(pos, &self.legals);
         self.turn = Some(pos.turn());
     }
 
     pub fn set_last_move(&mut self, m: Option<(Square, Square)>) {
-        self.last_move = m;
+        if m != self.last_move {
+            if self.last_move.is_some() {
+                self.previous_last_move = self.last_move;
+            }
+            self.last_move = m;
+            self.last_move_since = SteadyTime::now();
+        }
+    }
+
+    pub fn last_move(&self) -> Option<(Square, Square)> {
+        self.last_move
     }
 
     pub fn set_check(&mut self, king: Option<Square>) {
         self.check = king;
     }
 
+    pub fn check(&self) -> Option<Square> {
+        self.check
+    }
+
     pub fn set_turn(&mut self, turn: Option<Color>) {
         self.turn = turn;
     }
@@ -77,8 +287,34 @@ impl BoardState {
         self.legals.iter().filter(|m| m.from() == Some(orig)).map(Move::to).collect()
     }
 
+    /// Destinations of all legal moves from any of the `origs`.
+    pub fn hint_targets_from(&self, origs: Bitboard) -> Bitboard {
+        self.legals.iter()
+            .filter(|m| m.from().map_or(false, |sq| origs.contains(sq)))
+            .map(king_dest)
+            .collect()
+    }
+
+    /// Like `move_targets`, but castling moves target the square where the
+    /// king lands rather than the rook, in standard chess and Chess960.
+    pub fn hint_targets(&self, orig: Square) -> Bitboard {
+        self.legals.iter().filter(|m| m.from() == Some(orig)).map(king_dest).collect()
+    }
+
     pub fn valid_move(&self, orig: Square, dest: Square) -> bool {
-        self.move_targets(orig).contains(dest)
+        self.move_targets(orig).contains(dest) || self.hint_targets(orig).contains(dest)
+    }
+
+    /// Map a king move to its castling destination back to the rook
+    /// square, which is how castling moves are reported.
+    pub fn normalize_dest(&self, orig: Square, dest: Square) -> Square {
+        if self.move_targets(orig).contains(dest) {
+            return dest;
+        }
+
+        self.legals.iter()
+            .find(|m| m.is_castle() && m.from() == Some(orig) && king_dest(m) == dest)
+            .map_or(dest, Move::to)
     }
 
     pub fn legal_move(&self, orig: Square, dest: Square, promotion: Option<Role>) -> bool {
@@ -95,6 +331,18 @@ impl BoardState {
         &mut self.legals
     }
 
+    /// Set the SAN of each legal move, in the same order as `legals`.
+    pub fn set_sans(&mut self, sans: Vec<San>) {
+        self.sans = sans;
+    }
+
+    /// The legal move from `orig` to `dest`, with its SAN if known.
+    pub fn find_move(&self, orig: Square, dest: Square, promotion: Option<Role>) -> Option<(&Move, Option<&San>)> {
+        self.legals.iter()
+            .position(|m| m.from() == Some(orig) && m.to() == dest && m.promotion() == promotion)
+            .map(|i| (&self.legals[i], self.sans.get(i)))
+    }
+
     pub fn set_orientation(&mut self, orientation: Color) {
         self.orientation = orientation;
     }
@@ -103,40 +351,569 @@ impl BoardState {
         self.orientation
     }
 
+    pub fn set_show_move_hints(&mut self, show_move_hints: bool) {
+        self.show_move_hints = show_move_hints;
+    }
+
+    pub fn show_move_hints(&self) -> bool {
+        self.show_move_hints
+    }
+
+    pub fn set_max_size(&mut self, max_size: Option<i32>) {
+        self.max_size = max_size;
+    }
+
+    pub fn max_size(&self) -> Option<i32> {
+        self.max_size
+    }
+
+    pub fn set_drag_legal_only(&mut self, drag_legal_only: bool) {
+        self.drag_legal_only = drag_legal_only;
+    }
+
+    pub fn drag_legal_only(&self) -> bool {
+        self.drag_legal_only
+    }
+
+    pub fn set_drag_delay_ms(&mut self, drag_delay_ms: i64) {
+        self.drag_delay_ms = drag_delay_ms;
+    }
+
+    pub fn drag_delay_ms(&self) -> i64 {
+        self.drag_delay_ms
+    }
+
+    pub fn set_allow_null_drop(&mut self, allow_null_drop: bool) {
+        self.allow_null_drop = allow_null_drop;
+    }
+
+    pub fn allow_null_drop(&self) -> bool {
+        self.allow_null_drop
+    }
+
+    pub fn set_premove(&mut self, premove: Option<(Square, Square)>) {
+        self.premove = premove;
+    }
+
+    pub fn premove(&self) -> Option<(Square, Square)> {
+        self.premove
+    }
+
+    /// Set the square of the keyboard cursor.
+    pub fn set_cursor(&mut self, cursor: Option<Square>) {
+        self.cursor = cursor;
+    }
+
+    pub fn cursor(&self) -> Option<Square> {
+        self.cursor
+    }
+
+    pub fn set_premovable(&mut self, premovable: bool) {
+        self.premovable = premovable;
+    }
+
+    pub fn premovable(&self) -> bool {
+        self.premovable
+    }
+
+    /// The legal move for a premove in the current position, as its
+    /// destination and promotion. Premoves to the back rank promote to a
+    /// queen.
+    pub fn find_premove(&self, orig: Square, dest: Square) -> Option<(Square, Option<Role>)> {
+        let dest = self.normalize_dest(orig, dest);
+        [None, Some(Role::Queen)].iter()
+            .find(|&&promotion| self.legal_move(orig, dest, promotion))
+            .map(|&promotion| (dest, promotion))
+    }
+
+    pub fn set_switch_selection(&mut self, switch_selection: bool) {
+        self.switch_selection = switch_selection;
+    }
+
+    pub fn switch_selection(&self) -> bool {
+        self.switch_selection
+    }
+
+    pub fn set_spotlight(&mut self, spotlight: Bitboard) {
+        self.spotlight = spotlight;
+    }
+
+    pub fn set_hover_highlight(&mut self, hover_highlight: bool) {
+        self.hover_highlight = hover_highlight;
+        if !self.tracks_hover() {
+            self.hovered = None;
+        }
+    }
+
+    pub fn set_hover_piece_fade(&mut self, hover_piece_fade: Option<f64>) {
+        self.hover_piece_fade = hover_piece_fade;
+        if !self.tracks_hover() {
+            self.hovered = None;
+        }
+    }
+
+    pub fn hover_piece_fade(&self) -> Option<f64> {
+        self.hover_piece_fade
+    }
+
+    fn tracks_hover(&self) -> bool {
+        self.hover_highlight || self.hover_piece_fade.is_some()
+    }
+
+    /// Update the square under the pointer, returning the previous one if
+    /// it changed.
+    pub(crate) fn set_hovered(&mut self, square: Option<Square>) -> Option<Option<Square>> {
+        let square = square.filter(|_| self.tracks_hover());
+        if square != self.hovered {
+            Some(mem::replace(&mut self.hovered, square))
+        } else {
+            None
+        }
+    }
+
+    pub fn hovered(&self) -> Option<Square> {
+        self.hovered
+    }
+
+    pub fn set_drag_coordinate_tooltip(&mut self, drag_coordinate_tooltip: bool) {
+        self.drag_coordinate_tooltip = drag_coordinate_tooltip;
+    }
+
+    pub fn drag_coordinate_tooltip(&self) -> bool {
+        self.drag_coordinate_tooltip
+    }
+
+    pub fn set_castling_rooks(&mut self, castling_rooks: Bitboard) {
+        self.castling_rooks = castling_rooks;
+    }
+
+    /// Number coordinates as seen from the given side, independent of the
+    /// board orientation. `None` follows the board.
+    pub fn set_coordinate_orientation(&mut self, coordinate_orientation: Option<Color>) {
+        self.coordinate_orientation = coordinate_orientation;
+    }
+
+    pub fn set_piece_opacity(&mut self, piece_opacity: f64) {
+        self.piece_opacity = piece_opacity.max(0.0).min(1.0);
+    }
+
+    pub fn piece_opacity(&self) -> f64 {
+        self.piece_opacity
+    }
+
+    pub fn set_persist_hints_after_move(&mut self, persist_hints_after_move: bool) {
+        self.persist_hints_after_move = persist_hints_after_move;
+    }
+
+    pub fn persist_hints_after_move(&self) -> bool {
+        self.persist_hints_after_move
+    }
+
+    /// Tint the destinations of all legal moves of the given side.
+    pub fn set_all_targets(&mut self, all_targets: Option<Color>) {
+        self.all_targets = all_targets;
+    }
+
+    pub fn all_targets(&self) -> Option<Color> {
+        self.all_targets
+    }
+
+    pub fn set_confirm_moves(&mut self, confirm_moves: bool) {
+        self.confirm_moves = confirm_moves;
+        if !confirm_moves {
+            self.pending_move = None;
+        }
+    }
+
+    pub fn confirm_moves(&self) -> bool {
+        self.confirm_moves
+    }
+
+    /// Set the move previewed until the user confirms it.
+    pub fn set_pending_move(&mut self, pending_move: Option<(Square, Square)>) {
+        self.pending_move = pending_move;
+    }
+
+    pub fn pending_move(&self) -> Option<(Square, Square)> {
+        self.pending_move
+    }
+
+    /// Receive pointer motion while no button is held, which hover
+    /// effects need.
+    pub fn set_hover_events(&mut self, hover_events: bool) {
+        self.hover_events = hover_events;
+        if !hover_events {
+            self.hovered = None;
+        }
+    }
+
+    pub fn hover_events(&self) -> bool {
+        self.hover_events
+    }
+
+    /// Ignore attempts to select or move pieces. Shapes can still be
+    /// drawn.
+    pub fn set_view_only(&mut self, view_only: bool) {
+        self.view_only = view_only;
+    }
+
+    pub fn view_only(&self) -> bool {
+        self.view_only
+    }
+
+    /// The square the moving piece ends up on, which differs from the
+    /// destination for castling moves.
+    pub fn landing_square(&self, orig: Square, dest: Square) -> Square {
+        self.legals.iter()
+            .find(|m| m.from() == Some(orig) && m.to() == dest)
+            .map_or(dest, king_dest)
+    }
+
+    /// Let the highlight of a square pulse for a few seconds.
+    pub fn pulse_square(&mut self, square: Square) {
+        self.pulse = Some((square, SteadyTime::now()));
+    }
+
+    fn pulse_elapsed(&self) -> Option<(Square, i64)> {
+        self.pulse
+            .map(|(square, since)| (square, (SteadyTime::now() - since).num_milliseconds()))
+            .filter(|&(_, elapsed)| elapsed < PULSE_MS)
+    }
+
+    pub fn set_show_eval_bar(&mut self, show_eval_bar: bool) {
+        self.show_eval_bar = show_eval_bar;
+    }
+
+    pub fn show_eval_bar(&self) -> bool {
+        self.show_eval_bar
+    }
+
+    /// Set the evaluation in pawns from white's point of view, clamped to
+    /// +-10.
+    pub fn set_eval(&mut self, eval: f64) {
+        self.eval = eval.max(-10.0).min(10.0);
+    }
+
+    pub fn set_render_quality(&mut self, render_quality: RenderQuality) {
+        self.render_quality = render_quality;
+    }
+
+    pub fn set_coordinate_mode(&mut self, coordinate_mode: CoordinateMode) {
+        self.coordinate_mode = coordinate_mode;
+    }
+
+    /// Whether the board has a border for coordinates around it.
+    pub fn has_border(&self) -> bool {
+        match self.coordinate_mode {
+            CoordinateMode::Always | CoordinateMode::EmptyEdges => true,
+            CoordinateMode::InsideSquares | CoordinateMode::Hidden => false,
+        }
+    }
+
+    /// Rotate the board by a small angle in degrees, keeping pieces and
+    /// text upright.
+    pub fn set_tilt(&mut self, tilt: f64) {
+        self.tilt = tilt;
+    }
+
+    pub fn tilt(&self) -> f64 {
+        self.tilt
+    }
+
+    pub fn set_select_on_drag(&mut self, select_on_drag: bool) {
+        self.select_on_drag = select_on_drag;
+    }
+
+    pub fn select_on_drag(&self) -> bool {
+        self.select_on_drag
+    }
+
+    pub fn set_square_painter(&mut self, square_painter: Option<SquarePainter>) {
+        self.square_painter = square_painter.map(Rc::new);
+    }
+
+    pub fn set_highlight_overlap(&mut self, highlight_overlap: HighlightOverlap) {
+        self.highlight_overlap = highlight_overlap;
+    }
+
+    /// Check if the last move highlight on `square` gives way to the
+    /// selection.
+    pub(crate) fn hides_last_move(&self, square: Square, selected: Option<Square>) -> bool {
+        self.highlight_overlap == HighlightOverlap::SelectionWins && selected == Some(square)
+    }
+
+    /// Check if the selection highlight on `square` gives way to the last
+    /// move.
+    pub(crate) fn hides_selection(&self, square: Square) -> bool {
+        self.highlight_overlap == HighlightOverlap::LastMoveWins &&
+            self.last_move.map_or(false, |(orig, dest)| orig == square || dest == square)
+    }
+
+    pub fn set_animate_shapes(&mut self, animate_shapes: bool) {
+        self.animate_shapes = animate_shapes;
+    }
+
+    pub fn animate_shapes(&self) -> bool {
+        self.animate_shapes
+    }
+
+    pub fn set_promotion_layout(&mut self, promotion_layout: PromotionLayout) {
+        self.promotion_layout = promotion_layout;
+    }
+
+    pub fn promotion_layout(&self) -> PromotionLayout {
+        self.promotion_layout
+    }
+
+    pub fn set_immovable(&mut self, immovable: Bitboard) {
+        self.immovable = immovable;
+    }
+
+    pub fn immovable(&self) -> Bitboard {
+        self.immovable
+    }
+
+    pub fn set_animate_highlights(&mut self, animate_highlights: bool) {
+        self.animate_highlights = animate_highlights;
+    }
+
+    /// Progress of fading in a highlight that was set at `since`, or 1.0
+    /// if highlights are not animated.
+    pub fn highlight_fade(&self, since: SteadyTime) -> f64 {
+        if self.animate_highlights {
+            ease(0.0, 1.0, (SteadyTime::now() - since).num_milliseconds() as f64 / HIGHLIGHT_FADE_MS as f64)
+        } else {
+            1.0
+        }
+    }
+
+    pub(crate) fn queue_animation(&self, ctx: &WidgetContext) {
+        if let Some((square, _)) = self.pulse_elapsed() {
+            ctx.queue_draw_square(square);
+        }
+
+        if self.highlight_fade(self.last_move_since) < 1.0 {
+            for &(orig, dest) in self.last_move.iter().chain(self.previous_last_move.iter()) {
+                ctx.queue_draw_square(orig);
+                ctx.queue_draw_square(dest);
+            }
+        }
+    }
+
+    pub fn set_theme(&mut self, theme: BoardTheme) {
+        self.theme = theme;
+    }
+
+    pub fn theme(&self) -> &BoardTheme {
+        &self.theme
+    }
+
+    pub fn set_middle_click_deselects(&mut self, middle_click_deselects: bool) {
+        self.middle_click_deselects = middle_click_deselects;
+    }
+
+    pub fn middle_click_deselects(&self) -> bool {
+        self.middle_click_deselects
+    }
+
+    pub fn set_fixed_square_px(&mut self, fixed_square_px: Option<i32>) {
+        self.fixed_square_px = fixed_square_px;
+    }
+
+    pub fn fixed_square_px(&self) -> Option<i32> {
+        self.fixed_square_px
+    }
+
+    pub fn set_animate_promotions(&mut self, animate_promotions: bool) {
+        self.animate_promotions = animate_promotions;
+    }
+
+    pub fn animate_promotions(&self) -> bool {
+        self.animate_promotions
+    }
+
+    pub fn set_selection_style(&mut self, selection_style: SelectionStyle) {
+        self.selection_style = selection_style;
+    }
+
+    pub fn selection_style(&self) -> SelectionStyle {
+        self.selection_style
+    }
+
+    pub fn set_mirror_files(&mut self, mirror_files: bool) {
+        self.mirror_files = mirror_files;
+    }
+
+    pub fn mirror_files(&self) -> bool {
+        self.mirror_files
+    }
+
+    /// Undo the board rotation and mirroring, so that pieces and text are
+    /// drawn upright.
+    pub(crate) fn upright(&self, cr: &Context) {
+        let angle = self.orientation.fold_wb(0.0, PI) + self.tilt.to_radians();
+        cr.rotate(if self.mirror_files { angle } else { -angle });
+        if self.mirror_files {
+            cr.scale(-1.0, 1.0);
+        }
+    }
+
+    pub fn set_show_check(&mut self, show_check: bool) {
+        self.show_check = show_check;
+    }
+
+    pub fn set_board_svg(&mut self, board_svg: Option<Handle>) {
+        self.board_svg = board_svg;
+    }
+
+    pub fn set_piece_set(&mut self, piece_set: PieceSet) {
+        self.piece_set = piece_set;
+    }
+
     pub fn piece_set(&self) -> &PieceSet {
         &self.piece_set
     }
 
-    pub(crate) fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
-        self.draw_border(cr)?;
+    pub(crate) fn draw(&self, cr: &Context, selected: Option<Square>, occupied: Bitboard) -> Result<(), cairo::Error> {
+        cr.set_antialias(match self.render_quality {
+            RenderQuality::Smooth => Antialias::Default,
+            RenderQuality::Crisp => Antialias::None,
+        });
+
+        self.draw_border(cr, occupied)?;
+        self.draw_eval_bar(cr)?;
         self.draw_turn(cr)?;
         self.draw_board(cr)?;
-        self.draw_last_move(cr)?;
+        self.draw_inner_coordinates(cr)?;
+        self.draw_castling_rooks(cr)?;
+        self.draw_last_move(cr, selected)?;
+        self.draw_premove(cr)?;
+        self.draw_hovered(cr)?;
+        self.draw_cursor(cr)?;
+        self.draw_pulse(cr)?;
         self.draw_check(cr)?;
         Ok(())
     }
 
-    fn draw_border(&self, cr: &Context) -> Result<(), cairo::Error> {
-        cr.set_source_rgb(0.2, 0.2, 0.5);
+    pub(crate) fn draw_spotlight(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if self.spotlight.any() {
+            cr.save()?;
+            cr.set_fill_rule(FillRule::EvenOdd);
+            cr.rectangle(0.0, 0.0, 8.0, 8.0);
+            for square in self.spotlight {
+                cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+            }
+            cr.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+            cr.fill()?;
+            cr.restore()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_border(&self, cr: &Context, occupied: Bitboard) -> Result<(), cairo::Error> {
+        if !self.has_border() {
+            return Ok(());
+        }
+
+        let (r, g, b) = self.theme.border;
+        cr.set_source_rgb(r, g, b);
         cr.rectangle(-0.5, -0.5, 9.0, 9.0);
         cr.fill()?;
 
         cr.set_font_size(0.20);
-        cr.set_source_rgb(0.8, 0.8, 0.8);
+        let (r, g, b) = self.theme.coordinates;
+        cr.set_source_rgb(r, g, b);
+
+        let hidden = match self.coordinate_mode {
+            CoordinateMode::EmptyEdges => occupied,
+            _ => Bitboard(0),
+        };
 
-        for (rank, glyph) in ["1", "2", "3", "4", "5", "6", "7", "8"].iter().enumerate() {
-            self.draw_text(cr, (-0.25, 7.5 - rank as f64), glyph)?;
-            self.draw_text(cr, (8.25, 7.5 - rank as f64), glyph)?;
+        for (rank, glyph) in self.glyphs(RANK_GLYPHS).iter().enumerate() {
+            let rank = Rank::new(rank as u32);
+            if !hidden.contains(Square::from_coords(File::A, rank)) {
+                self.draw_text(cr, (-0.25, 7.5 - rank_to_float(rank)), glyph)?;
+            }
+            if !hidden.contains(Square::from_coords(File::H, rank)) {
+                self.draw_text(cr, (8.25, 7.5 - rank_to_float(rank)), glyph)?;
+            }
         }
 
-        for (file, glyph) in ["a", "b", "c", "d", "e", "f", "g", "h"].iter().enumerate() {
-            self.draw_text(cr, (0.5 + file as f64, -0.25), glyph)?;
-            self.draw_text(cr, (0.5 + file as f64, 8.25), glyph)?;
+        for (file, glyph) in self.glyphs(FILE_GLYPHS).iter().enumerate() {
+            let file = File::new(file as u32);
+            if !hidden.contains(Square::from_coords(file, Rank::Eighth)) {
+                self.draw_text(cr, (0.5 + file_to_float(file), -0.25), glyph)?;
+            }
+            if !hidden.contains(Square::from_coords(file, Rank::First)) {
+                self.draw_text(cr, (0.5 + file_to_float(file), 8.25), glyph)?;
+            }
         }
 
         Ok(())
     }
 
+    /// Draw file letters on the bottom rank and rank numbers on the left
+    /// file, as seen by the viewer, in the color of the other squares.
+    fn draw_inner_coordinates(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if self.coordinate_mode != CoordinateMode::InsideSquares {
+            return Ok(());
+        }
+
+        // board directions of the viewer's right and down
+        let down = self.orientation.fold_wb(1.0, -1.0);
+        let right = if self.mirror_files { -down } else { down };
+
+        let bottom = self.orientation.fold_wb(Rank::First, Rank::Eighth);
+        let left = if right > 0.0 { File::A } else { File::H };
+
+        cr.set_font_size(0.18);
+
+        let label = |square: Square, glyph: &str, (dx, dy): (f64, f64)| {
+            let (r, g, b) = if square.is_light() { self.theme.dark } else { self.theme.light };
+            cr.set_source_rgb(r, g, b);
+            let (x, y) = (0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank()));
+            self.draw_text(cr, (x + right * dx, y + down * dy), glyph)
+        };
+
+        for (file, glyph) in self.glyphs(FILE_GLYPHS).iter().enumerate() {
+            label(Square::from_coords(File::new(file as u32), bottom), glyph, (0.38, 0.36))?;
+        }
+
+        for (rank, glyph) in self.glyphs(RANK_GLYPHS).iter().enumerate() {
+            label(Square::from_coords(left, Rank::new(rank as u32)), glyph, (-0.40, -0.34))?;
+        }
+
+        Ok(())
+    }
+
+    /// Number coordinates from the other side, if requested.
+    fn glyphs(&self, mut labels: [&'static str; 8]) -> [&'static str; 8] {
+        if self.coordinate_orientation.map_or(false, |c| c != self.orientation) {
+            labels.reverse();
+        }
+        labels
+    }
+
+    fn draw_eval_bar(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if !self.show_eval_bar {
+            return Ok(());
+        }
+
+        // share of white, with diminishing returns for large advantages
+        let white = 1.0 / (1.0 + (-0.4 * self.eval).exp());
+
+        cr.set_source_rgb(0.1, 0.1, 0.1);
+        cr.rectangle(EVAL_BAR_LEFT, 0.0, EVAL_BAR_WIDTH, 8.0);
+        cr.fill()?;
+
+        cr.set_source_rgb(0.95, 0.95, 0.95);
+        cr.rectangle(EVAL_BAR_LEFT, 8.0 * (1.0 - white), EVAL_BAR_WIDTH, 8.0 * white);
+        cr.fill()?;
+
+        Ok(())
+    }
+
     fn draw_turn(&self, cr: &Context) -> Result<(), cairo::Error> {
         match self.turn {
             Some(Color::White) => {
@@ -155,13 +932,13 @@ impl BoardState {
         Ok(())
     }
 
-    fn draw_text(&self, cr: &Context, (x, y): (f64, f64), text: &str) -> Result<(), cairo::Error> {
+    pub(crate) fn draw_text(&self, cr: &Context, (x, y): (f64, f64), text: &str) -> Result<(), cairo::Error> {
         let font = cr.font_extents()?;
         let e = cr.text_extents(text)?;
 
         cr.save()?;
         cr.translate(x, y);
-        cr.rotate(self.orientation.fold_wb(0.0, PI));
+        self.upright(cr);
         cr.move_to(-0.5 * e.width, 0.5 * font.height - font.descent);
         cr.show_text(text)?;
         cr.restore()?;
@@ -169,12 +946,39 @@ impl BoardState {
         Ok(())
     }
 
-    fn draw_board(&self, cr: &Context) -> Result<(), cairo::Error> {
+    pub(crate) fn draw_board(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some(ref board_svg) = self.board_svg {
+            let dimensions = board_svg.dimensions();
+            if dimensions.width > 0 && dimensions.height > 0 {
+                cr.save()?;
+                cr.rectangle(0.0, 0.0, 8.0, 8.0);
+                cr.clip();
+                cr.scale(8.0 / f64::from(dimensions.width), 8.0 / f64::from(dimensions.height));
+                board_svg.render_cairo(cr);
+                cr.restore()?;
+                return Ok(());
+            }
+        }
+
+        if let Some(ref painter) = self.square_painter {
+            for square in Square::ALL {
+                cr.save()?;
+                cr.translate(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+                cr.rectangle(0.0, 0.0, 1.0, 1.0);
+                cr.clip();
+                painter(cr, square, square.is_light());
+                cr.restore()?;
+            }
+            return Ok(());
+        }
+
         cr.rectangle(0.0, 0.0, 8.0, 8.0);
-        cr.set_source_rgb(0.55, 0.64, 0.68); // dark
+        let (r, g, b) = self.theme.dark;
+        cr.set_source_rgb(r, g, b);
         cr.fill()?;
 
-        cr.set_source_rgb(0.87, 0.89, 0.90); // light
+        let (r, g, b) = self.theme.light;
+        cr.set_source_rgb(r, g, b);
 
         for square in Square::ALL {
             if square.is_light() {
@@ -186,29 +990,127 @@ impl BoardState {
         Ok(())
     }
 
-    fn draw_last_move(&self, cr: &Context) -> Result<(), cairo::Error> {
-        if let Some((orig, dest)) = self.last_move {
-            cr.set_source_rgba(0.61, 0.78, 0.0, 0.41);
+    fn draw_castling_rooks(&self, cr: &Context) -> Result<(), cairo::Error> {
+        let corner = 0.15;
+
+        cr.set_source_rgba(0.0, 0.0, 0.0, 0.3);
+
+        for square in self.castling_rooks {
+            let (x, y) = (file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+
+            cr.move_to(x, y);
+            cr.rel_line_to(corner, 0.0);
+            cr.rel_line_to(-corner, corner);
+            cr.close_path();
+
+            cr.move_to(x + 1.0, y + 1.0);
+            cr.rel_line_to(-corner, 0.0);
+            cr.rel_line_to(corner, -corner);
+            cr.close_path();
+
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_last_move(&self, cr: &Context, selected: Option<Square>) -> Result<(), cairo::Error> {
+        let fade = self.highlight_fade(self.last_move_since);
+        let (r, g, b, a) = self.theme.last_move;
+
+        if let Some(previous) = self.previous_last_move.filter(|_| fade < 1.0) {
+            cr.set_source_rgba(r, g, b, a * (1.0 - fade));
+            self.draw_move_squares(cr, previous, selected)?;
+        }
+
+        if let Some(last_move) = self.last_move {
+            cr.set_source_rgba(r, g, b, a * fade);
+            self.draw_move_squares(cr, last_move, selected)?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_premove(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some((orig, dest)) = self.premove {
+            let (r, g, b, a) = self.theme.premove_orig;
+            cr.set_source_rgba(r, g, b, a);
             cr.rectangle(file_to_float(orig.file()), 7.0 - rank_to_float(orig.rank()), 1.0, 1.0);
             cr.fill()?;
 
-            if dest != orig {
-                cr.rectangle(file_to_float(dest.file()), 7.0 - rank_to_float(dest.rank()), 1.0, 1.0);
-                cr.fill()?;
-            }
+            let (r, g, b, a) = self.theme.premove_dest;
+            cr.set_source_rgba(r, g, b, a);
+            cr.rectangle(file_to_float(dest.file()), 7.0 - rank_to_float(dest.rank()), 1.0, 1.0);
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_pulse(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some((square, elapsed)) = self.pulse_elapsed() {
+            let t = elapsed as f64 / PULSE_MS as f64;
+            let (r, g, b, a) = self.theme.selected;
+            cr.set_source_rgba(r, g, b, a * (PI * PULSES * t).sin().powi(2));
+            cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_hovered(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some(square) = self.hovered.filter(|_| self.hover_highlight) {
+            let (r, g, b, a) = self.theme.selected;
+            cr.set_source_rgba(r, g, b, a * 0.5);
+            cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_cursor(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if let Some(square) = self.cursor {
+            let (r, g, b, _) = self.theme.selected;
+            cr.set_source_rgb(r, g, b);
+            cr.set_line_width(0.08);
+            cr.rectangle(file_to_float(square.file()) + 0.04, 7.04 - rank_to_float(square.rank()), 0.92, 0.92);
+            cr.stroke()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_move_squares(&self, cr: &Context, (orig, dest): (Square, Square), selected: Option<Square>) -> Result<(), cairo::Error> {
+        if !self.hides_last_move(orig, selected) {
+            cr.rectangle(file_to_float(orig.file()), 7.0 - rank_to_float(orig.rank()), 1.0, 1.0);
+            cr.fill()?;
+        }
+
+        if dest != orig && !self.hides_last_move(dest, selected) {
+            cr.rectangle(file_to_float(dest.file()), 7.0 - rank_to_float(dest.rank()), 1.0, 1.0);
+            cr.fill()?;
         }
 
         Ok(())
     }
 
     fn draw_check(&self, cr: &Context) -> Result<(), cairo::Error> {
+        if !self.show_check {
+            return Ok(());
+        }
+
         if let Some(check) = self.check {
             let cx = 0.5 + file_to_float(check.file());
             let cy = 7.5 - rank_to_float(check.rank());
-            let gradient = RadialGradient::new(cx, cy, 0.0, cx, cy, 0.5f64.hypot(0.5));
-            gradient.add_color_stop_rgba(0.0, 1.0, 0.0, 0.0, 1.0);
-            gradient.add_color_stop_rgba(0.25, 0.91, 0.0, 0.0, 1.0);
-            gradient.add_color_stop_rgba(0.89, 0.66, 0.0, 0.0, 0.0);
+            let gradient = RadialGradient::new(cx, cy, 0.0, cx, cy, self.theme.check_glow_radius.max(0.0));
+            let (ir, ig, ib) = self.theme.check_inner;
+            let (or, og, ob) = self.theme.check_outer;
+            gradient.add_color_stop_rgba(0.0, ir, ig, ib, 1.0);
+            let mid = |inner: f64, outer: f64| inner + 0.25 * (outer - inner);
+            gradient.add_color_stop_rgba(0.25, mid(ir, or), mid(ig, og), mid(ib, ob), 1.0);
+            gradient.add_color_stop_rgba(0.89, or, og, ob, 0.0);
             cr.set_source(&gradient)?;
             cr.paint()?;
         }
@@ -216,3 +1118,65 @@ impl BoardState {
         Ok(())
     }
 }
+
+fn king_dest(m: &Move) -> Square {
+    match *m {
+        Move::Castle { king, rook } => {
+            let file = if rook.file() > king.file() { File::G } else { File::C };
+            Square::from_coords(file, king.rank())
+        }
+        _ => m.to(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use shakmaty::CastlingMode;
+    use shakmaty::fen::Fen;
+
+    fn state(fen: &str, mode: CastlingMode) -> BoardState {
+        let fen: Fen = fen.parse().expect("valid fen");
+        let pos: Chess = fen.position(mode).expect("legal position");
+        BoardState::from_position(&pos)
+    }
+
+    #[test]
+    fn test_hint_targets_castling() {
+        let state = state("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", CastlingMode::Standard);
+        let hints = state.hint_targets(Square::E1);
+        assert!(hints.contains(Square::G1));
+        assert!(hints.contains(Square::C1));
+        assert!(!hints.contains(Square::H1));
+        assert!(!hints.contains(Square::A1));
+        assert!(state.valid_move(Square::E1, Square::G1));
+        assert!(state.valid_move(Square::E1, Square::H1));
+        assert_eq!(state.normalize_dest(Square::E1, Square::G1), Square::H1);
+    }
+
+    #[test]
+    fn test_find_move() {
+        let mut state = BoardState::new();
+        let (m, san) = state.find_move(Square::G1, Square::F3, None).expect("legal move");
+        assert_eq!(m.to(), Square::F3);
+        assert_eq!(san.map(|san| san.to_string()), Some("Nf3".to_owned()));
+        assert!(state.find_move(Square::G1, Square::G3, None).is_none());
+
+        // legal moves without SAN, e.g. from UCI
+        state.set_sans(Vec::new());
+        let (_, san) = state.find_move(Square::G1, Square::F3, None).expect("legal move");
+        assert!(san.is_none());
+    }
+
+    #[test]
+    fn test_hint_targets_chess960_castling() {
+        let state = state("4k3/8/8/8/8/8/8/1R2K1R1 w KQ - 0 1", CastlingMode::Chess960);
+        let hints = state.hint_targets(Square::E1);
+        assert!(hints.contains(Square::G1));
+        assert!(hints.contains(Square::C1));
+        assert!(!hints.contains(Square::B1));
+        assert!(state.move_targets(Square::E1).contains(Square::B1));
+        assert_eq!(state.normalize_dest(Square::E1, Square::C1), Square::B1);
+    }
+}
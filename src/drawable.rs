@@ -14,16 +14,21 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use std::cmp::{min, max};
+use std::mem;
 use std::f64::consts::PI;
 
+use time::SteadyTime;
+
 use gdk::{EventButton, ModifierType};
 use cairo::Context;
 
 use shakmaty::Square;
 
-use ground::{EventContext, GroundMsg};
+use boardstate::BoardState;
+use ground::{EventContext, GroundMsg, WidgetContext};
 
-use util::{file_to_float, rank_to_float};
+use util::{ease, file_to_float, rank_to_float};
 
 /// Shape colors.
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -40,11 +45,24 @@ pub struct DrawShape {
     orig: Square,
     dest: Square,
     brush: DrawBrush,
+    locked: bool,
+    label: Option<String>,
+    /// Sideways displacement in thousandths of a square, so that shapes
+    /// stay `Eq`.
+    curve: i32,
 }
 
+const MAX_HISTORY: usize = 50;
+const CURVE_UNITS: f64 = 1000.0;
+const GROW_MS: i64 = 300;
+const LABEL_RADIUS: f64 = 0.17;
+
 pub struct Drawable {
     drawing: Option<DrawShape>,
     shapes: Vec<DrawShape>,
+    undo: Vec<Vec<DrawShape>>,
+    redo: Vec<Vec<DrawShape>>,
+    growing: Vec<(Square, Square, SteadyTime)>,
     enabled: bool,
     erase_on_click: bool,
 }
@@ -54,6 +72,9 @@ impl Drawable {
         Drawable {
             drawing: None,
             shapes: Vec::new(),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            growing: Vec::new(),
             enabled: true,
             erase_on_click: true,
         }
@@ -66,8 +87,7 @@ impl Drawable {
 
         match e.button() {
             1 => {
-                if self.erase_on_click && !self.shapes.is_empty() {
-                    self.shapes.clear();
+                if self.erase_on_click && self.clear_unlocked() {
                     ctx.stream().emit(GroundMsg::ShapesChanged(self.shapes.clone()));
                     ctx.widget().queue_draw();
                 }
@@ -84,11 +104,7 @@ impl Drawable {
                         DrawBrush::Green
                     };
 
-                    DrawShape {
-                        orig: square,
-                        dest: square,
-                        brush,
-                    }
+                    DrawShape::new(square, square, brush)
                 });
 
                 ctx.widget().queue_draw();
@@ -101,9 +117,10 @@ impl Drawable {
         if let Some(ref mut drawing) = self.drawing {
             let dest = ctx.square().unwrap_or(drawing.orig);
             if drawing.dest != dest {
-                ctx.widget().queue_draw();
+                drawing.queue_draw(ctx.widget());
+                drawing.dest = dest;
+                drawing.queue_draw(ctx.widget());
             }
-            drawing.dest = dest;
         }
     }
 
@@ -113,11 +130,12 @@ impl Drawable {
                 drawing.dest = ctx.square().unwrap_or(drawing.orig);
 
                 // remove or add shape
-                let num_shapes = self.shapes.len();
-                self.shapes.retain(|s| s.orig != drawing.orig || s.dest != drawing.dest);
-                if num_shapes == self.shapes.len() {
+                let previous = self.shapes.clone();
+                self.shapes.retain(|s| s.locked || s.orig != drawing.orig || s.dest != drawing.dest);
+                if previous.len() == self.shapes.len() {
                     self.shapes.push(drawing);
                 }
+                self.push_undo(previous);
 
                 ctx.stream().emit(GroundMsg::ShapesChanged(self.shapes.clone()));
             }
@@ -126,20 +144,186 @@ impl Drawable {
         }
     }
 
-    pub(crate) fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
+    /// Replace all shapes. If `animate`, shapes that were not shown before
+    /// grow from their origin. Clears the undo history, so that undo does
+    /// not bring back shapes from before.
+    pub fn set_shapes(&mut self, shapes: Vec<DrawShape>, animate: bool) {
+        if animate {
+            let now = SteadyTime::now();
+            for shape in &shapes {
+                if !self.shapes.iter().any(|s| s.orig == shape.orig && s.dest == shape.dest) {
+                    self.growing.push((shape.orig, shape.dest, now));
+                }
+            }
+        }
+
+        self.shapes = shapes;
+        self.clear_history();
+    }
+
+    fn progress(&self, shape: &DrawShape) -> f64 {
+        self.growing.iter()
+            .find(|&&(orig, dest, _)| orig == shape.orig && dest == shape.dest)
+            .map_or(1.0, |&(_, _, since)| {
+                ease(0.0, 1.0, (SteadyTime::now() - since).num_milliseconds() as f64 / GROW_MS as f64)
+            })
+    }
+
+    pub(crate) fn queue_animation(&mut self, ctx: &WidgetContext) {
+        if self.growing.is_empty() {
+            return;
+        }
+
+        for shape in &self.shapes {
+            if self.growing.iter().any(|&(orig, dest, _)| orig == shape.orig && dest == shape.dest) {
+                shape.queue_draw(ctx);
+            }
+        }
+
+        let now = SteadyTime::now();
+        self.growing.retain(|&(_, _, since)| (now - since).num_milliseconds() < GROW_MS);
+    }
+
+    pub fn shapes(&self) -> &[DrawShape] {
+        &self.shapes
+    }
+
+    fn clear_history(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+
+    fn push_undo(&mut self, shapes: Vec<DrawShape>) {
+        push_history(&mut self.undo, shapes);
+        self.redo.clear();
+    }
+
+    /// Restore the shapes before the last user interaction. Returns `false`
+    /// if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.undo.pop() {
+            Some(shapes) => {
+                push_history(&mut self.redo, mem::replace(&mut self.shapes, shapes));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restore the shapes of the last undone interaction. Returns `false`
+    /// if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.redo.pop() {
+            Some(shapes) => {
+                push_history(&mut self.undo, mem::replace(&mut self.shapes, shapes));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove all shapes, including locked ones. Returns `false` if there
+    /// were none.
+    pub fn clear(&mut self) -> bool {
+        self.growing.clear();
+
+        if self.shapes.is_empty() {
+            return false;
+        }
+
+        let previous = mem::take(&mut self.shapes);
+        self.push_undo(previous);
+        true
+    }
+
+    /// Remove the shapes that are not locked, like a click on the board.
+    /// Returns `false` if there were none.
+    fn clear_unlocked(&mut self) -> bool {
+        if self.shapes.iter().all(|s| s.locked) {
+            return false;
+        }
+
+        let previous = self.shapes.clone();
+        self.shapes.retain(|s| s.locked);
+        self.push_undo(previous);
+        true
+    }
+
+    /// Cancel a shape that the user is drawing. Returns `false` if there
+    /// was none.
+    pub fn cancel_drawing(&mut self) -> bool {
+        self.drawing.take().is_some()
+    }
+
+    /// Show a line of moves as numbered, locked arrows, replacing the
+    /// previously shown line. Clears the undo history like `set_shapes()`.
+    pub fn show_variation(&mut self, moves: &[(Square, Square)]) {
+        self.clear_history();
+        self.shapes.retain(|s| !s.locked || s.label.is_none());
+        for (i, &(orig, dest)) in moves.iter().enumerate() {
+            self.shapes.push(DrawShape::new(orig, dest, DrawBrush::Blue)
+                .with_locked(true)
+                .with_label((i + 1).to_string()));
+        }
+    }
+
+    pub(crate) fn draw(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
         for shape in &self.shapes {
-            shape.draw(cr)?;
+            shape.draw(cr, state, self.progress(shape))?;
         }
 
         if let Some(ref shape) = self.drawing {
-            shape.draw(cr)?;
+            shape.draw(cr, state, 1.0)?;
         }
 
         Ok(())
     }
 }
 
+/// Push to the undo or redo history, dropping the oldest entry when it is
+/// full.
+fn push_history(history: &mut Vec<Vec<DrawShape>>, shapes: Vec<DrawShape>) {
+    if history.len() >= MAX_HISTORY {
+        history.remove(0);
+    }
+    history.push(shapes);
+}
+
 impl DrawShape {
+    /// Create an arrow from `orig` to `dest`, or a circle if both squares
+    /// are the same.
+    pub fn new(orig: Square, dest: Square, brush: DrawBrush) -> DrawShape {
+        DrawShape {
+            orig,
+            dest,
+            brush,
+            locked: false,
+            label: None,
+            curve: 0,
+        }
+    }
+
+    /// Lock the shape, so that it is not removed when the user clears
+    /// shapes.
+    pub fn with_locked(mut self, locked: bool) -> DrawShape {
+        self.locked = locked;
+        self
+    }
+
+    /// Add a short label, like a move number, drawn on the shape.
+    pub fn with_label<S: Into<String>>(mut self, label: S) -> DrawShape {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Bend the arrow by displacing the middle of its path sideways, in
+    /// squares. Positive values bend to the left, as seen from the origin.
+    /// This separates overlapping arrows between the same squares.
+    pub fn with_curve(mut self, curve: f64) -> DrawShape {
+        self.curve = (curve * CURVE_UNITS).round() as i32;
+        self
+    }
+
     /// First square.
     pub fn orig(&self) -> Square {
         self.orig
@@ -150,11 +334,31 @@ impl DrawShape {
         self.dest
     }
 
+    /// Square the arrow points to, or `None` for circles.
+    pub fn arrow_dest(&self) -> Option<Square> {
+        if self.is_arrow() { Some(self.dest) } else { None }
+    }
+
     /// Shape color.
     pub fn brush(&self) -> DrawBrush {
         self.brush
     }
 
+    /// Label drawn on the shape.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Sideways displacement of a curved arrow.
+    pub fn curve(&self) -> f64 {
+        f64::from(self.curve) / CURVE_UNITS
+    }
+
+    /// Check if the shape is locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
     /// Check if the shape is a circle.
     pub fn is_circle(&self) -> bool {
         self.orig == self.dest
@@ -165,58 +369,183 @@ impl DrawShape {
         self.orig != self.dest
     }
 
-    fn draw(&self, cr: &Context) -> Result<(), cairo::Error> {
-        let opacity = 0.5;
+    /// Invalidate the area covered by the shape.
+    fn queue_draw(&self, ctx: &WidgetContext) {
+        let (x, y, width, height) = self.area();
+        ctx.queue_draw_rect(x, y, width, height);
+    }
+
+    /// The area covered by the shape. Arrow heads and circles stay within
+    /// the squares of their end points, curved arrows bend at most `curve`
+    /// beyond them. Labels of circles stick out above the square.
+    fn area(&self) -> (f64, f64, f64, f64) {
+        let bend = self.curve().abs();
+        let label = if self.is_circle() && self.label.is_some() { LABEL_RADIUS } else { 0.0 };
+        let min_x = file_to_float(min(self.orig.file(), self.dest.file())) - bend;
+        let max_x = 1.0 + file_to_float(max(self.orig.file(), self.dest.file())) + bend;
+        let min_y = 7.0 - rank_to_float(max(self.orig.rank(), self.dest.rank())) - bend - label;
+        let max_y = 8.0 - rank_to_float(min(self.orig.rank(), self.dest.rank())) + bend;
+        (min_x, min_y, max_x - min_x, max_y - min_y)
+    }
 
+    fn set_source(&self, cr: &Context, opacity: f64) {
         match self.brush {
             DrawBrush::Green => cr.set_source_rgba(0.08, 0.47, 0.11, opacity),
             DrawBrush::Red => cr.set_source_rgba(0.53, 0.13, 0.13, opacity),
             DrawBrush::Blue => cr.set_source_rgba(0.0, 0.19, 0.53, opacity),
             DrawBrush::Yellow => cr.set_source_rgba(0.90, 0.94, 0.0, opacity),
         }
+    }
+
+    /// Draw the shape, revealing only `progress` (from 0.0 to 1.0) of it
+    /// while it grows.
+    fn draw(&self, cr: &Context, state: &BoardState, progress: f64) -> Result<(), cairo::Error> {
+        self.set_source(cr, 0.5);
 
         let orig_x = 0.5 + file_to_float(self.orig.file());
         let orig_y = 7.5 - rank_to_float(self.orig.rank());
         let dest_x = 0.5 + file_to_float(self.dest.file());
         let dest_y = 7.5 - rank_to_float(self.dest.rank());
 
+        // control point of a curved arrow, displaced sideways from the
+        // middle
+        let (dx, dy) = (dest_x - orig_x, dest_y - orig_y);
+        let hypot = dx.hypot(dy);
+        let (ctrl_x, ctrl_y) = if self.is_arrow() {
+            (0.5 * (orig_x + dest_x) + dy * self.curve() / hypot,
+             0.5 * (orig_y + dest_y) - dx * self.curve() / hypot)
+        } else {
+            (dest_x, dest_y)
+        };
+
         if self.is_circle() {
             // draw circle
             let stroke = 0.05;
             cr.set_line_width(stroke);
-            cr.arc(dest_x, dest_y, 0.5 * (1.0 - stroke), 0.0, 2.0 * PI);
+            cr.arc(dest_x, dest_y, 0.5 * (1.0 - stroke), 0.0, 2.0 * PI * progress);
             cr.stroke()?;
         } else {
             // draw arrow
             let marker_size = 0.75;
             let margin = 0.1;
 
-            let (dx, dy) = (dest_x - orig_x, dest_y - orig_y);
-            let hypot = dx.hypot(dy);
+            // the head points along the end of the path
+            let (hx, hy) = (dest_x - ctrl_x, dest_y - ctrl_y);
+            let head_hypot = hx.hypot(hy);
 
-            let shaft_x = dest_x - dx * (marker_size + margin) / hypot;
-            let shaft_y = dest_y - dy * (marker_size + margin) / hypot;
+            let shaft_x = dest_x - hx * (marker_size + margin) / head_hypot;
+            let shaft_y = dest_y - hy * (marker_size + margin) / head_hypot;
 
-            let head_x = dest_x - dx * margin / hypot;
-            let head_y = dest_y - dy * margin / hypot;
+            let head_x = dest_x - hx * margin / head_hypot;
+            let head_y = dest_y - hy * margin / head_hypot;
 
             let stroke = 0.2;
             cr.set_line_width(stroke);
 
-            // shaft
+            // reveal from the origin while growing
+            cr.save()?;
+            cr.arc(orig_x, orig_y, progress * (hypot + self.curve().abs() + 0.5), 0.0, 2.0 * PI);
+            cr.clip();
+
+            // shaft, as a quadratic curve
             cr.move_to(orig_x, orig_y);
-            cr.line_to(shaft_x, shaft_y);
+            cr.curve_to(orig_x + 2.0 / 3.0 * (ctrl_x - orig_x), orig_y + 2.0 / 3.0 * (ctrl_y - orig_y),
+                        shaft_x + 2.0 / 3.0 * (ctrl_x - shaft_x), shaft_y + 2.0 / 3.0 * (ctrl_y - shaft_y),
+                        shaft_x, shaft_y);
             cr.stroke()?;
 
             // arrow head
             cr.move_to(head_x, head_y);
-            cr.line_to(shaft_x - dy * 0.5 * marker_size / hypot,
-                       shaft_y + dx * 0.5 * marker_size / hypot);
-            cr.line_to(shaft_x + dy * 0.5 * marker_size / hypot,
-                       shaft_y - dx * 0.5 * marker_size / hypot);
+            cr.line_to(shaft_x - hy * 0.5 * marker_size / head_hypot,
+                       shaft_y + hx * 0.5 * marker_size / head_hypot);
+            cr.line_to(shaft_x + hy * 0.5 * marker_size / head_hypot,
+                       shaft_y - hx * 0.5 * marker_size / head_hypot);
             cr.fill()?;
+
+            cr.restore()?;
+        }
+
+        if let Some(ref label) = self.label {
+            // label in the middle of the shape
+            let (x, y) = if self.is_circle() {
+                (dest_x, dest_y - 0.5)
+            } else {
+                (0.25 * (orig_x + dest_x) + 0.5 * ctrl_x, 0.25 * (orig_y + dest_y) + 0.5 * ctrl_y)
+            };
+
+            self.set_source(cr, 1.0);
+            cr.arc(x, y, LABEL_RADIUS, 0.0, 2.0 * PI);
+            cr.fill()?;
+
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+            cr.set_font_size(0.2);
+            state.draw_text(cr, (x, y), label)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clear_unlocked() {
+        let locked = DrawShape::new(Square::E2, Square::E4, DrawBrush::Blue).with_locked(true);
+        let user = DrawShape::new(Square::G1, Square::F3, DrawBrush::Green);
+
+        let mut drawable = Drawable::new();
+        drawable.set_shapes(vec![locked.clone(), user.clone()], false);
+        assert!(drawable.clear_unlocked());
+        assert_eq!(drawable.shapes(), &[locked.clone()]);
+
+        // nothing left to clear
+        assert!(!drawable.clear_unlocked());
+        assert_eq!(drawable.shapes(), &[locked.clone()]);
+
+        // the cleared shapes can be restored
+        assert!(drawable.undo());
+        assert_eq!(drawable.shapes(), &[locked, user]);
+    }
+
+    #[test]
+    fn test_circle_label_area() {
+        let circle = DrawShape::new(Square::E4, Square::E4, DrawBrush::Green);
+        let labeled = circle.clone().with_label("1");
+
+        // the label is centered on the top edge of the square
+        let (x, y, width, height) = labeled.area();
+        assert!(x <= 4.5 - LABEL_RADIUS && 4.5 + LABEL_RADIUS <= x + width);
+        assert!(y <= 4.5 - 0.5 - LABEL_RADIUS && 4.5 + 0.5 <= y + height);
+
+        let (_, y, _, _) = circle.area();
+        assert!((y - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_history_is_capped() {
+        let mut drawable = Drawable::new();
+        for _ in 0..2 * MAX_HISTORY {
+            drawable.push_undo(vec![DrawShape::new(Square::E2, Square::E4, DrawBrush::Red)]);
+        }
+        assert_eq!(drawable.undo.len(), MAX_HISTORY);
+
+        for _ in 0..2 * MAX_HISTORY {
+            drawable.redo.push(Vec::new());
+            assert!(drawable.redo());
+            assert!(drawable.undo());
+        }
+        assert!(drawable.undo.len() <= MAX_HISTORY);
+        assert!(drawable.redo.len() <= MAX_HISTORY);
+    }
+
+    #[test]
+    fn test_clear_removes_locked() {
+        let mut drawable = Drawable::new();
+        drawable.set_shapes(vec![DrawShape::new(Square::E2, Square::E4, DrawBrush::Blue).with_locked(true)], false);
+        assert!(drawable.clear());
+        assert!(drawable.shapes().is_empty());
+        assert!(!drawable.clear());
+    }
+}
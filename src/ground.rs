@@ -16,29 +16,46 @@
 
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
-use std::f64::consts::PI;
-use std::cmp::{min, max};
+use std::cmp::max;
 use std::fmt;
+use std::fs;
+use std::path::Path;
+#[cfg(feature = "profiling")]
+use std::collections::VecDeque;
+#[cfg(feature = "profiling")]
+use std::time::{Duration, Instant};
 
 use gtk::prelude::*;
 use gtk::DrawingArea;
-use gdk::{EventButton, EventMotion, EventMask};
-use cairo::{Context, Matrix};
+use gdk::{EventButton, EventKey, EventMotion, EventMask};
+use gdk::keys::Key;
+use gdk::keys::constants as key;
+use cairo::{Context, Format, ImageSurface, Matrix, SvgSurface};
+use rsvg::Handle;
 
 use relm::{Relm, Widget, Update, StreamHandle};
 
-use shakmaty::{Square, Rank, Color, Role, Board, Move, MoveList, Chess, Position};
+use shakmaty::{Square, File, Rank, Color, Role, Piece, Board, Bitboard, Move, MoveList, Chess, Position, CastlingMode};
+use shakmaty::fen::Fen;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
 
-use util::{file_to_float, pos_to_square, rank_to_float};
-use pieces::Pieces;
+use util::{board_extent, board_matrix, compute_matrix, damage_rect, fixed_size, play_on_board, pos_to_square, sans, square_area, uci_to_move};
+use pieces::{Pieces, BoardDiff};
 use drawable::{Drawable, DrawShape};
 use promotable::Promotable;
-use boardstate::BoardState;
+use boardstate::{BoardState, SelectionStyle, PromotionLayout, HighlightOverlap, SquarePainter, CoordinateMode, RenderQuality, Easing};
+use theme::BoardTheme;
+use pieceset::PieceSet;
 
-type Stream = StreamHandle<GroundMsg>;
+pub(crate) type Stream = StreamHandle<GroundMsg>;
+
+#[cfg(feature = "profiling")]
+const FRAME_STATS_LEN: usize = 120;
 
 pub struct Model {
     state: Rc<RefCell<State>>,
+    stream: Stream,
 }
 
 impl fmt::Debug for Model {
@@ -50,19 +67,235 @@ impl fmt::Debug for Model {
 /// Chessground events and messages.
 #[derive(Debug, Msg)]
 pub enum GroundMsg {
-    /// Flip the board.
+    /// Flip the board, toggling the orientation without having to track
+    /// it.
     Flip,
-    /// Set the board orientation.
+    /// Set the board orientation. Does nothing if the board already has
+    /// this orientation.
     SetOrientation(Color),
     /// Set up a position configuration.
     SetPos(Pos),
     /// Set up a board.
     SetBoard(Board),
+    /// Place pieces part way between two boards, with `t` from 0.0 to 1.0,
+    /// e.g. while dragging a replay slider.
+    InterpolatePositions { from: Board, to: Board, t: f64 },
+    /// Play a move on the board, as if it had been made by the host, e.g.
+    /// when replaying a game. The move must be one of the legal moves. The
+    /// resulting position has no legal moves until the next position is
+    /// set.
+    MakeMove(Square, Square, Option<Role>),
+    /// Set the side to move. If `in_check`, the king of that side on the
+    /// current board is highlighted as in check.
+    SetSideToMove { color: Color, in_check: bool },
+    /// Replace the legal moves on the current board with moves in UCI
+    /// notation, e.g. from an engine. Moves that the side to move cannot
+    /// make on the board, like promotions that do not reach the back rank,
+    /// are skipped.
+    SetLegalUci(Vec<String>),
+    /// Set up a board from a list of pieces, without any other hints.
+    SetPieces(Vec<(Square, Piece)>),
+    /// Set up the position after playing a move in UCI notation from
+    /// a FEN, with the last move and check hints.
+    ApplyUci { fen: String, uci: String },
+    /// Skip running animations.
+    FinishAnimations,
+    /// Hold running piece animations mid-flight, e.g. for slow-motion
+    /// demonstrations.
+    PauseAnimation,
+    /// Continue animations held by `PauseAnimation`.
+    ResumeAnimation,
+    /// Configure the easing of sliding pieces. Defaults to
+    /// `Easing::InOutCubic`.
+    SetSlideEasing(Easing),
+    /// Configure the easing of pieces fading in or out. Defaults to
+    /// `Easing::InOutCubic`.
+    SetFadeEasing(Easing),
+    /// Configure the duration of piece animations in seconds, e.g. to
+    /// reduce motion or speed up replays. `0.0` moves pieces instantly.
+    /// Defaults to `0.3`.
+    SetAnimationDuration(f64),
+    /// Show or hide legal move hints for the selected piece, including the
+    /// tint of the move target under a dragged piece. The selected square
+    /// stays highlighted.
+    SetShowMoveHints(bool),
+    /// Limit the size of the board (including the border) in pixels.
+    /// The board is centered in larger allocations.
+    SetMaxSize(Option<i32>),
+    /// Only allow picking up pieces that have legal moves.
+    SetDragLegalOnly(bool),
+    /// Only start dragging after the pointer has been held down for the
+    /// given number of milliseconds, so that a quick tap on a touchscreen
+    /// selects instead. Defaults to `0`.
+    SetDragDelay(i64),
+    /// Send `UserMove` with the same origin and destination when a piece
+    /// is dragged and dropped back onto its square, e.g. to pass.
+    SetAllowNullDrop(bool),
+    /// Highlight a move as the last move without changing the position,
+    /// e.g. while hovering a move list.
+    SetLastMove(Option<(Square, Square)>),
+    /// Highlight the origin and destination of a premove.
+    SetPremove(Option<(Square, Square)>),
+    /// Let the user queue a move with a piece of the side not to move. It
+    /// is sent as `UserMove` as soon as a new position makes it legal, or
+    /// cancelled otherwise. Defaults to `false`.
+    SetPremovable(bool),
+    /// Configure if clicking another friendly piece that can move selects
+    /// it, instead of attempting an illegal move. Enabled by default.
+    SetSwitchSelection(bool),
+    /// Undo the last change to user drawn shapes. Shapes set by the host
+    /// with `SetShapes` or `ShowVariation` start a new history.
+    UndoShape,
+    /// Redo the last undone change to user drawn shapes.
+    RedoShape,
+    /// Remove all shapes, including locked ones, and cancel a shape the
+    /// user is drawing. Can be undone with `UndoShape`.
+    ClearShapes,
+    /// Dim all squares except the given ones. An empty set turns the
+    /// spotlight off.
+    SetSpotlight(Bitboard),
+    /// Prevent the pieces on the given squares from being selected or
+    /// dragged. An empty set makes all pieces movable again.
+    SetImmovable(Bitboard),
+    /// Tint the square under the pointer, regardless of whether pieces can
+    /// be moved.
+    SetHoverHighlight(bool),
+    /// Receive pointer motion events while no button is held. Disabling
+    /// this reduces the event volume for apps that do not need hover
+    /// effects. Defaults to `true`.
+    SetHoverEvents(bool),
+    /// Mark the starting squares of the castling rooks, e.g. to explain
+    /// Chess960 setups. An empty set removes the marks.
+    SetCastlingRooks(Bitboard),
+    /// Multiply the opacity of the piece under the pointer, so that the
+    /// square color shows through. `None` turns the effect off.
+    SetHoverPieceFade(Option<f64>),
+    /// Arrange the promotion choices in a column or a grid.
+    SetPromotionLayout(PromotionLayout),
+    /// Show the name of the square under a dragged piece next to it.
+    SetDragCoordinateTooltip(bool),
+    /// Show a line of moves as numbered arrows. The arrows are locked and
+    /// replace the previously shown line.
+    ShowVariation(Vec<(Square, Square)>),
+    /// Replace all shapes, e.g. to restore annotations saved from
+    /// `Ground::shapes()`. Locked shapes are kept when the user clears
+    /// shapes.
+    SetShapes(Vec<DrawShape>),
+    /// Select the piece on a square and show its move hints, as if the
+    /// user clicked it. Ignored if the piece cannot be moved or the board
+    /// is view only.
+    SelectSquare(Square),
+    /// Clear the selection.
+    ClearSelection,
+    /// Do not let the user select or move pieces, e.g. for spectators or
+    /// game replays. Shapes can still be drawn. Defaults to `false`.
+    SetViewOnly(bool),
+    /// Ask for a second tap on the destination square before sending a
+    /// completed move as `UserMove`, e.g. to avoid misclicks on touch
+    /// screens. Defaults to `false`.
+    SetConfirmMoves(bool),
+    /// Tint every square that a legal move of the given side can reach,
+    /// e.g. to show beginners the activity of the pieces. Cleared when a
+    /// piece is selected.
+    ShowAllTargets(Color),
+    /// Clear the overlay of `ShowAllTargets`.
+    ClearHighlights,
+    /// Draw all pieces with the given opacity, from 0.0 to 1.0, e.g. to
+    /// show the board faintly in the background. Defaults to `1.0`.
+    SetPieceOpacity(f64),
+    /// Let the highlight of a square pulse for a few seconds, e.g. to
+    /// reveal a hint.
+    PulseSquare(Square),
+    /// Keep showing the hints of a piece after the user moved it, until
+    /// the next click, to help thinking about follow-ups. Defaults to
+    /// `false`.
+    SetPersistHintsAfterMove(bool),
+    /// Number coordinates as seen from the given side, independent of the
+    /// board orientation. `None` follows the board.
+    SetCoordinateOrientation(Option<Color>),
+    /// Show an evaluation bar next to the board.
+    SetShowEvalBar(bool),
+    /// Set the evaluation shown in the evaluation bar, in pawns from
+    /// white's point of view. Clamped to +-10.
+    SetEval(f64),
+    /// Turn antialiasing of squares, highlights and shapes on or off.
+    SetRenderQuality(RenderQuality),
+    /// Draw coordinates next to all edge squares, only next to empty
+    /// ones, inside the edge squares or not at all. The last two leave out
+    /// the border, e.g. for tight layouts.
+    SetCoordinateMode(CoordinateMode),
+    /// Tilt the board by an angle in degrees for a stylized look. Pieces
+    /// stay upright and clicks still map to the right squares.
+    SetTilt(f64),
+    /// Configure if dragging a piece selects it, showing its move hints
+    /// while dragging. Defaults to `true`.
+    SetSelectOnDrag(bool),
+    /// Choose the highlight shown on a selected square that is also part of
+    /// the last move.
+    SetHighlightOverlap(HighlightOverlap),
+    /// Let shapes added by `SetShapes` grow from their origin.
+    SetAnimateShapes(bool),
+    /// Fade selection and last move highlights in and out.
+    SetAnimateHighlights(bool),
+    /// Set the board colors.
+    SetTheme(BoardTheme),
+    /// Configure if a middle click clears the selection, like a right
+    /// click. Other buttons never affect the selection.
+    SetMiddleClickDeselects(bool),
+    /// Render squares with an exact size in pixels, instead of scaling the
+    /// board to fill the widget. The widget requests the resulting size.
+    SetFixedSquarePx(Option<i32>),
+    /// Let promoted pieces grow into place instead of just appearing.
+    SetAnimatePromotions(bool),
+    /// Set how the selected square is highlighted.
+    SetSelectionStyle(SelectionStyle),
+    /// Mirror the board horizontally, so that files run from h to a when
+    /// viewed from white's side. Coordinates stay with their files.
+    SetMirrorFiles(bool),
+    /// Show or hide the check hint, e.g. for variants where the king can
+    /// be captured.
+    SetShowCheck(bool),
+    /// Draw the 8x8 board from SVG data instead of plain colored squares.
+    /// Data that cannot be parsed is ignored.
+    SetBoardSvg(Option<Vec<u8>>),
+    /// Replace the piece images, e.g. with a set loaded by
+    /// `PieceSet::from_dir()`.
+    SetPieceSet(PieceSet),
 
     /// Sent when the completed a piece drag or move.
     UserMove(Square, Square, Option<Role>),
+    /// Sent when the user selects a piece, or `None` when the user clears
+    /// the selection.
+    SelectionChanged(Option<Square>),
+    /// Sent when the user queued a premove.
+    PremoveSet(Square, Square),
+    /// Sent when a queued premove was cancelled by a click or because it
+    /// is not legal in the new position.
+    PremoveCancelled,
+    /// Sent when the user completed a move that awaits confirmation.
+    MovePreview(Square, Square),
+    /// Sent after a legal user move, with the move in UCI notation and,
+    /// if the position configuration was created from a position, in SAN
+    /// without check and checkmate suffixes.
+    MovePlayed { uci: String, san: Option<String> },
+    /// Sent when the move of `MakeMove` is not legal.
+    MakeMoveFailed(Square, Square, Option<Role>),
+    /// Sent when the FEN or move of `ApplyUci` is invalid.
+    ApplyUciFailed(String),
     /// Sent when shapes are added, removed or cleared.
     ShapesChanged(Vec<DrawShape>),
+    /// Sent when the user starts dragging the piece from the given square.
+    DragStarted(Square),
+    /// Sent when a drag ends, with the origin square and the square the
+    /// piece was dropped on, if any. Not sent if a new position cancels
+    /// the drag.
+    DragEnded(Square, Option<Square>),
+    /// Sent when a new position captures the piece on the given square.
+    /// For en passant, this is the square of the captured pawn.
+    Captured(Square),
+    /// Sent when a new position puts the king on the given square in
+    /// check, and it was not in check before.
+    CheckRaised(Square),
 }
 
 /// A position configuration.
@@ -76,6 +309,7 @@ pub enum GroundMsg {
 pub struct Pos {
     board: Board,
     legals: Box<MoveList>,
+    sans: Vec<San>,
     check: Option<Square>,
     last_move: Option<(Square, Square)>,
     turn: Option<Color>,
@@ -84,9 +318,12 @@ pub struct Pos {
 impl Pos {
     /// Create a new position configuration.
     pub fn new<P: Position>(p: &P) -> Pos {
+        let legals = p.legal_moves();
+
         Pos {
             board: p.board().clone(),
-            legals: Box::new(p.legal_moves()),
+            sans: sans(p, &legals),
+            legals: Box::new(legals),
             check: if p.checkers().any() { p.board().king_of(p.turn()) } else { None },
             last_move: None,
             turn: Some(p.turn()),
@@ -98,12 +335,22 @@ impl Pos {
         Pos {
             board,
             legals: Box::new(MoveList::new()),
+            sans: Vec::new(),
             check: None,
             last_move: None,
             turn: None,
         }
     }
 
+    fn from_fen_and_uci(fen: &str, uci: &str) -> Result<Pos, String> {
+        let fen: Fen = fen.parse().map_err(|err| format!("invalid fen: {}", err))?;
+        let mut pos: Chess = fen.position(CastlingMode::Standard).map_err(|err| format!("illegal fen: {}", err))?;
+        let uci: Uci = uci.parse().map_err(|err| format!("invalid uci: {}", err))?;
+        let m = uci.to_move(&pos).map_err(|err| format!("illegal uci: {}", err))?;
+        pos.play_unchecked(&m);
+        Ok(Pos::new(&pos).with_last_move(&m))
+    }
+
     /// Set the hint for the last move, so that it can be highlighted on
     /// the board.
     pub fn set_last_move(&mut self, m: Option<&Move>) {
@@ -126,12 +373,15 @@ impl Pos {
     }
 
     /// Set the legal move hints.
+    ///
+    /// Moves will no longer be reported in SAN.
     pub fn set_legals(&mut self, legals: MoveList) {
         self.legals = Box::new(legals);
+        self.sans.clear();
     }
 
     pub fn with_legals(mut self, legals: MoveList) -> Pos {
-        self.legals = Box::new(legals);
+        self.set_legals(legals);
         self
     }
 
@@ -152,21 +402,188 @@ impl Default for Pos {
     }
 }
 
+/// Initial configuration of the widget, so that it comes up showing a
+/// custom position instead of briefly showing the starting position.
+#[derive(Debug, Clone)]
+pub struct GroundInit {
+    /// Position configuration to show, including legal moves and the last
+    /// move.
+    pub pos: Pos,
+    /// Board orientation.
+    pub orientation: Color,
+}
+
+impl GroundInit {
+    /// Show the given position from white's point of view.
+    pub fn new(pos: Pos) -> GroundInit {
+        GroundInit {
+            pos,
+            orientation: Color::White,
+        }
+    }
+
+    /// Show the position from the point of view of `orientation`.
+    pub fn with_orientation(mut self, orientation: Color) -> GroundInit {
+        self.orientation = orientation;
+        self
+    }
+}
+
+impl Default for GroundInit {
+    fn default() -> GroundInit {
+        GroundInit::new(Pos::default())
+    }
+}
+
 /// Chessground, a chess board widget.
+///
+/// The accessors, like `selected()` or `squares()`, borrow the widget
+/// state and must not be called from within a draw or event callback of
+/// the widget.
 #[derive(Debug)]
 pub struct Ground {
     drawing_area: DrawingArea,
     model: Model,
 }
 
+impl Ground {
+    /// The origin square and current board-space position of the piece
+    /// that is being dragged, if any.
+    pub fn dragging(&self) -> Option<(Square, (f64, f64))> {
+        self.model.state.borrow().pieces.dragging()
+    }
+
+    /// The highlighted choice while the promotion dialog is open. The
+    /// arrow keys change it and enter confirms it.
+    pub fn promotion_candidate(&self) -> Option<Role> {
+        let state = self.model.state.borrow();
+        state.promotable.candidate(&state.board_state)
+    }
+
+    /// The selected square, if any.
+    ///
+    /// Borrows the widget state, so it panics if called from within a draw
+    /// or event callback of the widget.
+    pub fn selected(&self) -> Option<Square> {
+        self.model.state.borrow().pieces.selected()
+    }
+
+    /// Destinations of the legal moves from `orig`. Castling moves target
+    /// the rook, as in `UserMove`.
+    ///
+    /// Borrows the widget state, so it panics if called from within a draw
+    /// or event callback of the widget.
+    pub fn move_targets(&self, orig: Square) -> Bitboard {
+        self.model.state.borrow().board_state.move_targets(orig)
+    }
+
+    /// The shapes currently drawn, including those drawn by the user.
+    ///
+    /// Borrows the widget state, so it panics if called from within a draw
+    /// or event callback of the widget.
+    pub fn shapes(&self) -> Vec<DrawShape> {
+        self.model.state.borrow().drawable.shapes().to_vec()
+    }
+
+    /// The pieces currently shown, indexed by square.
+    pub fn squares(&self) -> [Option<Piece>; 64] {
+        let board = self.model.state.borrow().pieces.board();
+        let mut squares = [None; 64];
+        for (square, piece) in board {
+            squares[usize::from(square)] = Some(piece);
+        }
+        squares
+    }
+
+    /// The square under a pixel position relative to the widget, e.g. of
+    /// a right click, or `None` if the position is off the board.
+    pub fn square_at_pixel(&self, x: f64, y: f64) -> Option<Square> {
+        let state = self.model.state.borrow();
+        let ctx = WidgetContext::new(&state.board_state, &self.drawing_area);
+        let alloc = self.drawing_area.allocation();
+        pos_to_square(ctx.invert_pos((x + f64::from(alloc.x()), y + f64::from(alloc.y()))))
+    }
+
+    /// Paint squares with a custom function instead of the theme colors,
+    /// e.g. to draw patterns or textures. `None` restores the default.
+    pub fn set_square_painter(&self, square_painter: Option<SquarePainter>) {
+        self.model.state.borrow_mut().board_state.set_square_painter(square_painter);
+        self.drawing_area.queue_draw();
+    }
+
+    /// Check if a square is light.
+    pub fn is_light_square(&self, square: Square) -> bool {
+        square.is_light()
+    }
+
+    /// The color a square is filled with, according to the current theme.
+    pub fn square_color(&self, square: Square) -> (f64, f64, f64) {
+        let state = self.model.state.borrow();
+        let theme = state.board_state.theme();
+        if square.is_light() { theme.light } else { theme.dark }
+    }
+
+    /// Render the board while playing moves from a position, capturing
+    /// `fps` frames per second of animation as `size` x `size` images.
+    ///
+    /// Shapes and the current selection are not included.
+    pub fn render_frames<P: Position + Clone>(&self, pos: &P, moves: &[Move], fps: u32, size: i32) -> Result<Vec<ImageSurface>, cairo::Error> {
+        self.model.state.borrow().render_frames(pos, moves, fps, size)
+    }
+
+    /// Render only the squares and pieces of the current board, without
+    /// the border, highlights or shapes, e.g. for small icons.
+    pub fn render_icon(&self, size: i32) -> Result<ImageSurface, cairo::Error> {
+        self.model.state.borrow().render_icon(size)
+    }
+
+    /// Save the board as a PNG image of `size` pixels, with pieces at
+    /// rest on their squares.
+    pub fn render_to_png(&self, path: &Path, size: i32) -> Result<(), cairo::IoError> {
+        let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+        self.model.state.borrow().render_still(&Context::new(&surface)?, size)?;
+        surface.write_to_png(&mut fs::File::create(path)?)
+    }
+
+    /// Save the board as an SVG diagram of `size` points, with pieces at
+    /// rest on their squares.
+    pub fn render_to_svg(&self, path: &Path, size: i32) -> Result<(), cairo::Error> {
+        let surface = SvgSurface::new(f64::from(size), f64::from(size), Some(path))?;
+        self.model.state.borrow().render_still(&Context::new(&surface)?, size)?;
+        surface.finish();
+        Ok(())
+    }
+
+    /// Draw the board with everything on it into a caller-supplied
+    /// context, fitted into the square at `x`, `y` with side `size` in
+    /// user space, e.g. to composite several boards into one canvas.
+    pub fn draw_into(&self, cr: &Context, x: f64, y: f64, size: f64) -> Result<(), cairo::Error> {
+        self.model.state.borrow().draw_into(cr, x, y, size)
+    }
+
+    /// Durations of the most recently drawn frames, oldest first.
+    #[cfg(feature = "profiling")]
+    pub fn frame_stats(&self) -> Vec<Duration> {
+        self.model.state.borrow().frame_times.iter().cloned().collect()
+    }
+
+    /// Move all pieces to their final positions and drop pieces that are
+    /// fading out, skipping any running animations.
+    pub fn finish_animations(&self) {
+        self.model.state.borrow_mut().pieces.finish_animations();
+        self.drawing_area.queue_draw();
+    }
+}
+
 impl Update for Ground {
     type Model = Model;
-    type ModelParam = ();
+    type ModelParam = GroundInit;
     type Msg = GroundMsg;
 
-    fn model(_: &Relm<Self>, _: ()) -> Model {
+    fn model(relm: &Relm<Self>, init: GroundInit) -> Model {
         Model {
-            state: Rc::new(RefCell::new(State::new())),
+            state: Rc::new(RefCell::new(State::new(init))),
+            stream: relm.stream().clone(),
         }
     }
 
@@ -175,41 +592,327 @@ impl Update for Ground {
 
         match event {
             GroundMsg::Flip => {
-                let orientation = state.board_state.orientation();
-                state.board_state.set_orientation(!orientation);
+                let orientation = !state.board_state.orientation();
+                state.set_orientation(orientation, &self.model.stream);
                 self.drawing_area.queue_draw();
             },
             GroundMsg::SetOrientation(orientation) => {
-                state.board_state.set_orientation(orientation);
-                self.drawing_area.queue_draw();
+                if state.set_orientation(orientation, &self.model.stream) {
+                    self.drawing_area.queue_draw();
+                }
             },
             GroundMsg::SetPos(pos) => {
-                state.pieces.set_board(&pos.board);
-                state.promotable.update(&pos.legals);
-                state.board_state.set_check(pos.check);
-                state.board_state.set_last_move(pos.last_move);
-                state.board_state.set_turn(pos.turn);
-                *state.board_state.legals_mut() = *pos.legals;
-                self.drawing_area.queue_draw();
+                let dirty = state.set_pos(pos, &self.model.stream);
+                state.queue_draw_dirty(&self.drawing_area, dirty);
+            },
+            GroundMsg::ApplyUci { fen, uci } => {
+                match Pos::from_fen_and_uci(&fen, &uci) {
+                    Ok(pos) => {
+                        let dirty = state.set_pos(pos, &self.model.stream);
+                        state.queue_draw_dirty(&self.drawing_area, dirty);
+                    }
+                    Err(err) => self.model.stream.emit(GroundMsg::ApplyUciFailed(err)),
+                }
             },
             GroundMsg::SetBoard(board) => {
-                state.pieces.set_board(&board);
-                state.board_state.set_check(None);
-                state.board_state.set_last_move(None);
-                state.board_state.set_turn(None);
-                state.board_state.legals_mut().clear();
+                state.set_board(&board);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::InterpolatePositions { from, to, t } => {
+                state.pieces.interpolate(&from, &to, t);
                 state.promotable.cancel();
                 self.drawing_area.queue_draw();
             },
-            GroundMsg::UserMove(orig, dest, None) if state.board_state.valid_move(orig, dest) => {
-                if state.board_state.legals().iter().any(|m| m.from() == Some(orig) && m.to() == dest && m.promotion().is_some()) {
-                    let color = state.pieces.figurine_at(orig).map_or_else(|| {
-                        Color::from_white(dest.rank() > Rank::Fourth)
-                    }, |figurine| figurine.piece().color);
-                    state.promotable.start(color, orig, dest);
+            GroundMsg::MakeMove(orig, dest, promotion) => {
+                if state.make_move(orig, dest, promotion) {
+                    self.drawing_area.queue_draw();
+                } else {
+                    self.model.stream.emit(GroundMsg::MakeMoveFailed(orig, dest, promotion));
+                }
+            },
+            GroundMsg::SetSideToMove { color, in_check } => {
+                let check = if in_check { state.pieces.board().king_of(color) } else { None };
+                state.board_state.set_turn(Some(color));
+                state.board_state.set_check(check);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetLegalUci(ucis) => {
+                state.set_legal_uci(&ucis);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPieces(pieces) => {
+                let mut board = Board::empty();
+                for (square, piece) in pieces {
+                    board.set_piece_at(square, piece);
+                }
+                state.set_board(&board);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::PauseAnimation => {
+                state.pieces.pause_animations();
+            },
+            GroundMsg::ResumeAnimation => {
+                state.pieces.resume_animations();
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetSlideEasing(easing) => {
+                state.pieces.set_slide_easing(easing);
+            },
+            GroundMsg::SetFadeEasing(easing) => {
+                state.pieces.set_fade_easing(easing);
+            },
+            GroundMsg::SetAnimationDuration(animation_duration) => {
+                state.pieces.set_animation_duration(animation_duration);
+            },
+            GroundMsg::FinishAnimations => {
+                state.pieces.finish_animations();
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetShowMoveHints(show_move_hints) => {
+                state.board_state.set_show_move_hints(show_move_hints);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetMaxSize(max_size) => {
+                state.board_state.set_max_size(max_size);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetDragLegalOnly(drag_legal_only) => {
+                state.board_state.set_drag_legal_only(drag_legal_only);
+            },
+            GroundMsg::SetDragDelay(ms) => {
+                state.board_state.set_drag_delay_ms(ms);
+            },
+            GroundMsg::SetAllowNullDrop(allow_null_drop) => {
+                state.board_state.set_allow_null_drop(allow_null_drop);
+            },
+            GroundMsg::SetLastMove(last_move) => {
+                let previous = state.board_state.last_move();
+                state.board_state.set_last_move(last_move);
+
+                // only the squares of the old and new highlight change
+                let ctx = WidgetContext::new(&state.board_state, &self.drawing_area);
+                for (orig, dest) in previous.into_iter().chain(last_move) {
+                    ctx.queue_draw_square(orig);
+                    ctx.queue_draw_square(dest);
+                }
+            },
+            GroundMsg::SetPremove(premove) => {
+                state.board_state.set_premove(premove);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPremovable(premovable) => {
+                state.board_state.set_premovable(premovable);
+            },
+            GroundMsg::PremoveSet(orig, dest) => {
+                state.board_state.set_premove(Some((orig, dest)));
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetSwitchSelection(switch_selection) => {
+                state.board_state.set_switch_selection(switch_selection);
+            },
+            GroundMsg::UndoShape => {
+                if state.drawable.undo() {
+                    self.model.stream.emit(GroundMsg::ShapesChanged(state.drawable.shapes().to_vec()));
+                    self.drawing_area.queue_draw();
+                }
+            },
+            GroundMsg::RedoShape => {
+                if state.drawable.redo() {
+                    self.model.stream.emit(GroundMsg::ShapesChanged(state.drawable.shapes().to_vec()));
+                    self.drawing_area.queue_draw();
+                }
+            },
+            GroundMsg::ClearShapes => {
+                let cancelled = state.drawable.cancel_drawing();
+                let cleared = state.drawable.clear();
+                if cleared {
+                    self.model.stream.emit(GroundMsg::ShapesChanged(Vec::new()));
+                }
+                if cancelled || cleared {
+                    self.drawing_area.queue_draw();
+                }
+            },
+            GroundMsg::SetSpotlight(spotlight) => {
+                state.board_state.set_spotlight(spotlight);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetHoverHighlight(hover_highlight) => {
+                state.board_state.set_hover_highlight(hover_highlight);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetHoverEvents(hover_events) => {
+                state.board_state.set_hover_events(hover_events);
+                let motion = motion_mask(hover_events);
+
+                if let Some(window) = self.drawing_area.window().filter(|_| self.drawing_area.is_realized()) {
+                    let events = window.events() - EventMask::POINTER_MOTION_MASK - EventMask::BUTTON_MOTION_MASK;
+                    window.set_events(events | motion);
+                } else {
+                    let events = self.drawing_area.events() - EventMask::POINTER_MOTION_MASK - EventMask::BUTTON_MOTION_MASK;
+                    self.drawing_area.set_events(events | motion);
+                }
+
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetHoverPieceFade(hover_piece_fade) => {
+                state.board_state.set_hover_piece_fade(hover_piece_fade);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPromotionLayout(promotion_layout) => {
+                state.board_state.set_promotion_layout(promotion_layout);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetDragCoordinateTooltip(drag_coordinate_tooltip) => {
+                state.board_state.set_drag_coordinate_tooltip(drag_coordinate_tooltip);
+            },
+            GroundMsg::SetCastlingRooks(castling_rooks) => {
+                state.board_state.set_castling_rooks(castling_rooks);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetImmovable(immovable) => {
+                state.board_state.set_immovable(immovable);
+            },
+            GroundMsg::ShowVariation(moves) => {
+                state.drawable.show_variation(&moves);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetShapes(shapes) => {
+                let animate = state.board_state.animate_shapes();
+                state.drawable.set_shapes(shapes, animate);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SelectSquare(square) => {
+                let state = &mut *state;
+                if !state.board_state.view_only() && state.pieces.select(&state.board_state, square) {
+                    state.board_state.set_all_targets(None);
                     self.drawing_area.queue_draw();
                 }
             },
+            GroundMsg::SetViewOnly(view_only) => {
+                state.set_view_only(view_only, &self.model.stream);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetConfirmMoves(confirm_moves) => {
+                state.board_state.set_confirm_moves(confirm_moves);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::MovePreview(orig, dest) => {
+                state.board_state.set_pending_move(Some((orig, dest)));
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::ShowAllTargets(color) => {
+                state.board_state.set_all_targets(Some(color));
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::ClearHighlights => {
+                state.board_state.set_all_targets(None);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::ClearSelection => {
+                state.pieces.clear_selection();
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPieceOpacity(piece_opacity) => {
+                state.board_state.set_piece_opacity(piece_opacity);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::PulseSquare(square) => {
+                state.board_state.pulse_square(square);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPersistHintsAfterMove(persist_hints_after_move) => {
+                state.board_state.set_persist_hints_after_move(persist_hints_after_move);
+            },
+            GroundMsg::SetCoordinateOrientation(coordinate_orientation) => {
+                state.board_state.set_coordinate_orientation(coordinate_orientation);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetShowEvalBar(show_eval_bar) => {
+                state.board_state.set_show_eval_bar(show_eval_bar);
+                request_size(&self.drawing_area, &state.board_state);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetEval(eval) => {
+                state.board_state.set_eval(eval);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetRenderQuality(render_quality) => {
+                state.board_state.set_render_quality(render_quality);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetCoordinateMode(coordinate_mode) => {
+                state.board_state.set_coordinate_mode(coordinate_mode);
+                request_size(&self.drawing_area, &state.board_state);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetTilt(tilt) => {
+                state.board_state.set_tilt(tilt);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetSelectOnDrag(select_on_drag) => {
+                state.board_state.set_select_on_drag(select_on_drag);
+            },
+            GroundMsg::SetHighlightOverlap(highlight_overlap) => {
+                state.board_state.set_highlight_overlap(highlight_overlap);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetAnimateShapes(animate_shapes) => {
+                state.board_state.set_animate_shapes(animate_shapes);
+            },
+            GroundMsg::SetAnimateHighlights(animate_highlights) => {
+                state.board_state.set_animate_highlights(animate_highlights);
+            },
+            GroundMsg::SetTheme(theme) => {
+                state.board_state.set_theme(theme);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetMiddleClickDeselects(middle_click_deselects) => {
+                state.board_state.set_middle_click_deselects(middle_click_deselects);
+            },
+            GroundMsg::SetFixedSquarePx(fixed_square_px) => {
+                state.board_state.set_fixed_square_px(fixed_square_px);
+                request_size(&self.drawing_area, &state.board_state);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetAnimatePromotions(animate_promotions) => {
+                state.board_state.set_animate_promotions(animate_promotions);
+            },
+            GroundMsg::SetSelectionStyle(selection_style) => {
+                state.board_state.set_selection_style(selection_style);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetMirrorFiles(mirror_files) => {
+                state.board_state.set_mirror_files(mirror_files);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetShowCheck(show_check) => {
+                state.board_state.set_show_check(show_check);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetBoardSvg(data) => {
+                state.board_state.set_board_svg(data.and_then(|data| Handle::from_data(&data).ok()));
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::SetPieceSet(piece_set) => {
+                state.board_state.set_piece_set(piece_set);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::UserMove(orig, dest, None) if state.board_state.valid_move(orig, dest) &&
+                    state.board_state.legals().iter().any(|m| m.from() == Some(orig) && m.to() == dest && m.promotion().is_some()) => {
+                let color = state.pieces.figurine_at(orig).map_or_else(|| {
+                    Color::from_white(dest.rank() > Rank::Fourth)
+                }, |figurine| figurine.piece().color);
+                state.promotable.start(color, orig, dest);
+                self.drawing_area.queue_draw();
+            },
+            GroundMsg::UserMove(orig, dest, promotion) => {
+                if let Some((m, san)) = state.board_state.find_move(orig, dest, promotion) {
+                    self.model.stream.emit(GroundMsg::MovePlayed {
+                        uci: Uci::from_standard(m).to_string(),
+                        san: san.map(|san| san.to_string()),
+                    });
+                }
+            },
             _ => {}
         }
     }
@@ -225,9 +928,13 @@ impl Widget for Ground {
     fn view(relm: &Relm<Self>, model: Model) -> Self {
         let drawing_area = DrawingArea::new();
 
+        let hover_events = model.state.borrow().board_state.hover_events();
+
         drawing_area.add_events(EventMask::BUTTON_PRESS_MASK |
                                 EventMask::BUTTON_RELEASE_MASK |
-                                EventMask::POINTER_MOTION_MASK |
+                                motion_mask(hover_events) |
+                                EventMask::LEAVE_NOTIFY_MASK |
+                                EventMask::KEY_PRESS_MASK |
                                 EventMask::SCROLL_MASK);
 
         {
@@ -235,15 +942,25 @@ impl Widget for Ground {
             let weak_state = Rc::downgrade(&model.state);
             drawing_area.connect_draw(move |widget, cr| {
                 if let Some(state) = weak_state.upgrade() {
-                    let state = state.borrow();
+                    let mut state = state.borrow_mut();
+
+                    #[cfg(feature = "profiling")]
+                    let start = Instant::now();
+
                     state.draw(widget, cr).unwrap();
 
+                    #[cfg(feature = "profiling")]
+                    state.record_frame(start.elapsed());
+
                     // queue next draw for animation
                     let weak_state = Weak::clone(&weak_state);
                     let widget = widget.clone();
                     cairo::glib::idle_add_local(move || {
-                        if let Some(state) = weak_state.upgrade() {
-                            state.borrow_mut().queue_animation(&widget);
+                        // pause while hidden, resumed by the map handler
+                        if widget.is_mapped() {
+                            if let Some(state) = weak_state.upgrade() {
+                                state.borrow_mut().queue_animation(&widget);
+                            }
                         }
                         Continue(false)
                     });
@@ -252,12 +969,21 @@ impl Widget for Ground {
             });
         }
 
+        // resume animations when shown again
+        drawing_area.connect_map(|widget| widget.queue_draw());
+
         {
             // mouse down
             let state = Rc::downgrade(&model.state);
             let stream = relm.stream().clone();
             drawing_area.connect_button_press_event(move |widget, e| {
                 if let Some(state) = state.upgrade() {
+                    // take focus for keyboard move entry, but not when
+                    // drawing shapes or on a view only board
+                    if e.button() == 1 && !state.borrow().board_state.view_only() {
+                        widget.grab_focus();
+                    }
+
                     let mut state = state.borrow_mut();
                     state.button_press_event(&stream, widget, e);
                 }
@@ -291,6 +1017,32 @@ impl Widget for Ground {
             });
         }
 
+        {
+            // key press
+            let state = Rc::downgrade(&model.state);
+            let stream = relm.stream().clone();
+            drawing_area.connect_key_press_event(move |widget, e| {
+                if let Some(state) = state.upgrade() {
+                    let mut state = state.borrow_mut();
+                    return state.key_press_event(&stream, widget, e);
+                }
+                Inhibit(false)
+            });
+        }
+
+        {
+            // mouse leave
+            let state = Rc::downgrade(&model.state);
+            drawing_area.connect_leave_notify_event(move |widget, _e| {
+                if let Some(state) = state.upgrade() {
+                    let mut state = state.borrow_mut();
+                    state.set_hovered(widget, None);
+                }
+                Inhibit(false)
+            });
+        }
+
+        drawing_area.set_can_focus(true);
         drawing_area.set_hexpand(true);
         drawing_area.set_vexpand(true);
         drawing_area.show();
@@ -307,64 +1059,512 @@ struct State {
     drawable: Drawable,
     promotable: Promotable,
     pieces: Pieces,
+    #[cfg(feature = "profiling")]
+    frame_times: VecDeque<Duration>,
 }
 
 impl State {
-    fn new() -> State {
-        State {
+    fn new(init: GroundInit) -> State {
+        let mut state = State {
             board_state: BoardState::new(),
             drawable: Drawable::new(),
             promotable: Promotable::new(),
-            pieces: Pieces::new(),
+            pieces: Pieces::new_from_board(&init.pos.board),
+            #[cfg(feature = "profiling")]
+            frame_times: VecDeque::with_capacity(FRAME_STATS_LEN),
+        };
+
+        state.board_state.set_orientation(init.orientation);
+        state.load_pos(init.pos);
+        state
+    }
+
+    #[cfg(feature = "profiling")]
+    fn record_frame(&mut self, duration: Duration) {
+        if self.frame_times.len() >= FRAME_STATS_LEN {
+            self.frame_times.pop_front();
         }
+        self.frame_times.push_back(duration);
+    }
+
+    /// Check if setting the position would not change anything.
+    fn shows_pos(&self, pos: &Pos) -> bool {
+        self.pieces.shows_board(&pos.board) &&
+            self.board_state.check() == pos.check &&
+            self.board_state.last_move() == pos.last_move &&
+            self.board_state.turn() == pos.turn &&
+            *self.board_state.legals() == *pos.legals
+    }
+
+    /// Set up the position, sending `CheckRaised` for a new check and
+    /// `Captured` for captured pieces. Returns the squares that need to be
+    /// repainted, or `None` if the change is not confined to a few squares.
+    fn set_pos(&mut self, pos: Pos, stream: &Stream) -> Option<Bitboard> {
+        // keep a pending move or open promotion dialog when the same
+        // position is set again
+        if self.shows_pos(&pos) {
+            return Some(Bitboard(0));
+        }
+
+        let previous_check = self.board_state.check();
+        let previous_last_move = self.board_state.last_move();
+        let previous_premove = self.board_state.premove();
+        let previous_turn = self.board_state.turn();
+
+        // hints, dialogs and drags depend on more than the changed squares
+        let confined = self.pieces.selected().is_none() &&
+                       self.pieces.dragging().is_none() &&
+                       !self.promotable.is_open() &&
+                       self.board_state.pending_move().is_none() &&
+                       self.board_state.all_targets().is_none();
+
+        let diff = self.load_pos(pos);
+
+        for square in diff.captured {
+            stream.emit(GroundMsg::Captured(square));
+        }
+
+        if let Some(check) = self.board_state.check().filter(|&check| previous_check != Some(check)) {
+            stream.emit(GroundMsg::CheckRaised(check));
+        }
+
+        self.play_premove(stream);
+
+        // the check glow may reach beyond the king square
+        if !confined || previous_check.is_some() || self.board_state.check().is_some() {
+            return None;
+        }
+
+        let mut dirty = diff.changed;
+
+        let moves = previous_last_move.into_iter().chain(self.board_state.last_move())
+            .chain(previous_premove).chain(self.board_state.premove());
+        for (orig, dest) in moves {
+            dirty.add(orig);
+            dirty.add(dest);
+        }
+
+        // the turn indicator is in the margin next to these corners
+        if previous_turn != self.board_state.turn() {
+            dirty.add(Square::H1);
+            dirty.add(Square::H8);
+        }
+
+        Some(dirty)
+    }
+
+    /// Repaint the squares returned by `set_pos`, or everything.
+    fn queue_draw_dirty(&self, drawing_area: &DrawingArea, dirty: Option<Bitboard>) {
+        match dirty {
+            Some(dirty) => {
+                let ctx = WidgetContext::new(&self.board_state, drawing_area);
+                for square in dirty {
+                    ctx.queue_draw_square(square);
+                }
+            },
+            None => drawing_area.queue_draw(),
+        }
+    }
+
+    /// Play the queued premove once it is the turn of its piece, or cancel
+    /// it if it is not legal.
+    fn play_premove(&mut self, stream: &Stream) {
+        let (orig, dest) = match self.board_state.premove().filter(|_| self.board_state.premovable()) {
+            Some(premove) => premove,
+            None => return,
+        };
+
+        let color = self.pieces.figurine_at(orig).map(|f| f.piece().color);
+        if color.is_some() && color != self.board_state.turn() {
+            // still waiting for the opponent
+            return;
+        }
+
+        self.board_state.set_premove(None);
+
+        match self.board_state.find_premove(orig, dest) {
+            Some((dest, promotion)) => stream.emit(GroundMsg::UserMove(orig, dest, promotion)),
+            None => stream.emit(GroundMsg::PremoveCancelled),
+        }
+    }
+
+    /// Set up the position without sending events.
+    fn load_pos(&mut self, pos: Pos) -> BoardDiff {
+        let diff = self.pieces.set_board(&pos.board);
+        self.promotable.update(&pos.legals);
+        self.board_state.set_pending_move(None);
+        self.board_state.set_check(pos.check);
+        self.board_state.set_last_move(pos.last_move);
+        self.board_state.set_turn(pos.turn);
+        *self.board_state.legals_mut() = *pos.legals;
+        self.board_state.set_sans(pos.sans);
+        diff
+    }
+
+    fn make_move(&mut self, orig: Square, dest: Square, promotion: Option<Role>) -> bool {
+        let dest = self.board_state.normalize_dest(orig, dest);
+        let m = match self.board_state.legals().iter().find(|m| {
+            m.from() == Some(orig) && m.to() == dest && m.promotion() == promotion
+        }) {
+            Some(m) => m.clone(),
+            None => return false,
+        };
+
+        let turn = self.board_state.turn().unwrap_or(Color::White);
+        let mut board = self.pieces.board();
+        play_on_board(&mut board, &m, turn);
+
+        self.pieces.set_board(&board);
+        self.promotable.cancel();
+        self.board_state.set_pending_move(None);
+        self.board_state.set_last_move(Some((orig, m.to())));
+        self.board_state.set_check(None);
+        self.board_state.set_turn(Some(!turn));
+        self.board_state.legals_mut().clear();
+        self.board_state.set_sans(Vec::new());
+        true
+    }
+
+    fn set_legal_uci(&mut self, ucis: &[String]) {
+        let board = self.pieces.board();
+        let mut legals = MoveList::new();
+        legals.extend(ucis.iter()
+            .filter_map(|uci| uci.parse::<Uci>().ok())
+            .filter_map(|uci| uci_to_move(&board, self.board_state.turn(), &uci)));
+
+        self.promotable.update(&legals);
+        *self.board_state.legals_mut() = legals;
+        self.board_state.set_sans(Vec::new());
+    }
+
+    /// Set the orientation, cancelling a drag that would otherwise jump to
+    /// the mirrored position under the pointer. Returns `false` if the
+    /// orientation did not change.
+    fn set_orientation(&mut self, orientation: Color, stream: &Stream) -> bool {
+        if self.board_state.orientation() == orientation {
+            return false;
+        }
+
+        if let Some(square) = self.pieces.cancel_drag() {
+            stream.emit(GroundMsg::DragEnded(square, None));
+        }
+
+        self.board_state.set_orientation(orientation);
+        true
+    }
+
+    /// Enter or leave view-only mode, abandoning any interaction in
+    /// progress.
+    fn set_view_only(&mut self, view_only: bool, stream: &Stream) {
+        if view_only {
+            if let Some(square) = self.pieces.cancel_drag() {
+                stream.emit(GroundMsg::DragEnded(square, None));
+            }
+            self.pieces.clear_selection();
+            self.promotable.cancel();
+            self.board_state.set_pending_move(None);
+        }
+
+        self.board_state.set_view_only(view_only);
+    }
+
+    fn set_board(&mut self, board: &Board) {
+        self.pieces.set_board(board);
+        self.board_state.set_check(None);
+        self.board_state.set_last_move(None);
+        self.board_state.set_turn(None);
+        self.board_state.legals_mut().clear();
+        self.board_state.set_sans(Vec::new());
+        self.promotable.cancel();
+        self.board_state.set_pending_move(None);
     }
 
     fn queue_animation(&mut self, drawing_area: &DrawingArea) {
         let ctx = WidgetContext::new(&self.board_state, drawing_area);
+        self.board_state.queue_animation(&ctx);
         self.pieces.queue_animation(&ctx);
         self.promotable.queue_animation(&ctx);
+        self.drawable.queue_animation(&ctx);
     }
 
     fn draw(&self, drawing_area: &DrawingArea, cr: &Context) -> Result<(), cairo::Error> {
+        // nothing to draw before the widget has been allocated
+        let alloc = drawing_area.allocation();
+        if alloc.width() <= 0 || alloc.height() <= 0 {
+            return Ok(());
+        }
+
         let ctx = WidgetContext::new(&self.board_state, drawing_area);
         cr.set_matrix(ctx.matrix());
+        render(cr, &self.board_state, &self.pieces, &self.promotable, &self.drawable)
+    }
 
-        // draw
-        self.board_state.draw(cr)?;
-        self.pieces.draw(cr, &self.board_state, &self.promotable)?;
-        self.drawable.draw(cr)?;
-        self.pieces.draw_drag(cr, &self.board_state)?;
-        self.promotable.draw(cr, &self.board_state)?;
+    fn draw_into(&self, cr: &Context, x: f64, y: f64, size: f64) -> Result<(), cairo::Error> {
+        // a degenerate matrix would put the context into an error state
+        if size <= 0.0 {
+            return Ok(());
+        }
 
-        Ok(())
+        let tilt = self.board_state.tilt().to_radians();
+        let square_size = size / board_extent(&self.board_state) / (tilt.cos().abs() + tilt.sin().abs());
+
+        cr.save()?;
+        cr.transform(board_matrix(&self.board_state, x + size / 2.0, y + size / 2.0, square_size));
+        let result = render(cr, &self.board_state, &self.pieces, &self.promotable, &self.drawable);
+        cr.restore()?;
+        result
+    }
+
+    fn render_frames<P: Position + Clone>(&self, pos: &P, moves: &[Move], fps: u32, size: i32) -> Result<Vec<ImageSurface>, cairo::Error> {
+        // leave the highlights of the widget itself alone
+        let mut board_state = self.board_state.clone();
+
+        let mut pos = pos.clone();
+        let mut pieces = Pieces::new_from_board(pos.board());
+        let steps = max((f64::from(fps) * self.pieces.animation_duration()).ceil() as u32, 1);
+        let mut frames = Vec::new();
+
+        board_state.set_last_move(None);
+        let mut result = render_frame(&mut board_state, &pos, &pieces, size).map(|frame| frames.push(frame));
+
+        for m in moves {
+            if result.is_err() {
+                break;
+            }
+
+            pos.play_unchecked(m);
+            pieces.set_board(pos.board());
+            board_state.set_last_move(Some((m.from().unwrap_or_else(|| m.to()), m.to())));
+
+            for step in 1..=steps {
+                pieces.set_animation_progress(f64::from(step) / f64::from(steps));
+                result = render_frame(&mut board_state, &pos, &pieces, size).map(|frame| frames.push(frame));
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+
+        result.map(|_| frames)
+    }
+
+    fn render_still(&self, cr: &Context, size: i32) -> Result<(), cairo::Error> {
+        cr.set_matrix(compute_matrix(&self.board_state, 0, 0, size, size));
+        let pieces = Pieces::new_from_board(&self.pieces.board());
+        render(cr, &self.board_state, &pieces, &Promotable::new(), &self.drawable)
+    }
+
+    fn render_icon(&self, size: i32) -> Result<ImageSurface, cairo::Error> {
+        let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+        {
+            let cr = Context::new(&surface)?;
+            let half = f64::from(size) / 2.0;
+            cr.set_matrix(board_matrix(&self.board_state, half, half, f64::from(size) / 8.0));
+
+            self.board_state.draw_board(&cr)?;
+            self.pieces.draw_plain(&cr, &self.board_state)?;
+        }
+        Ok(surface)
     }
 
     fn button_release_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventButton) {
+        let selected = self.pieces.selected();
         let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
-        self.pieces.drag_mouse_up(&ctx);
+        if !self.board_state.view_only() {
+            self.pieces.drag_mouse_up(&ctx);
+        }
         self.drawable.mouse_up(&ctx);
+        self.emit_selection_changed(stream, selected);
     }
 
     fn motion_notify_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventMotion) {
-        let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
-        self.promotable.mouse_move(&ctx);
-        self.pieces.drag_mouse_move(&ctx);
-        self.drawable.mouse_move(&ctx);
+        let selected = self.pieces.selected();
+        let square = {
+            let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
+            if !self.board_state.view_only() {
+                self.promotable.mouse_move(&ctx);
+                self.pieces.drag_mouse_move(&ctx);
+            }
+            self.drawable.mouse_move(&ctx);
+            ctx.square()
+        };
+
+        self.emit_selection_changed(stream, selected);
+        self.set_hovered(drawing_area, square);
+    }
+
+    /// Send `SelectionChanged` if the selection differs from `previous`.
+    fn emit_selection_changed(&self, stream: &Stream, previous: Option<Square>) {
+        let selected = self.pieces.selected();
+        if selected != previous {
+            stream.emit(GroundMsg::SelectionChanged(selected));
+        }
+    }
+
+    fn set_hovered(&mut self, drawing_area: &DrawingArea, square: Option<Square>) {
+        if let Some(previous) = self.board_state.set_hovered(square) {
+            let ctx = WidgetContext::new(&self.board_state, drawing_area);
+            for square in previous.into_iter().chain(square) {
+                ctx.queue_draw_square(square);
+            }
+        }
+    }
+
+    fn key_press_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventKey) -> Inhibit {
+        if self.board_state.view_only() {
+            return Inhibit(false);
+        }
+
+        if self.promotable.is_open() {
+            let ctx = WidgetContext::new(&self.board_state, drawing_area);
+            return self.promotable.key_press(&mut self.pieces, &self.board_state, stream, &ctx, &e.keyval());
+        }
+
+        let selected = self.pieces.selected();
+        let inhibit = self.cursor_key_press(stream, drawing_area, &e.keyval());
+        self.emit_selection_changed(stream, selected);
+        inhibit
+    }
+
+    /// Move the keyboard cursor with the arrow keys, act on its square
+    /// with enter or space, and cancel with escape.
+    fn cursor_key_press(&mut self, stream: &Stream, drawing_area: &DrawingArea, keyval: &Key) -> Inhibit {
+        // board directions of the viewer's up and right
+        let up = self.board_state.orientation().fold_wb(1, -1);
+        let right = if self.board_state.mirror_files() { -up } else { up };
+
+        let (df, dr) = match *keyval {
+            key::Up => (0, up),
+            key::Down => (0, -up),
+            key::Left => (-right, 0),
+            key::Right => (right, 0),
+            key::Return | key::KP_Enter | key::space => {
+                if let Some(square) = self.board_state.cursor() {
+                    self.pieces.key_select(&self.board_state, stream, square);
+                    drawing_area.queue_draw();
+                }
+                return Inhibit(true);
+            }
+            key::Escape => {
+                if let Some(square) = self.pieces.cancel_drag() {
+                    stream.emit(GroundMsg::DragEnded(square, None));
+                }
+                if self.pieces.selected().is_some() {
+                    self.pieces.clear_selection();
+                } else {
+                    self.board_state.set_cursor(None);
+                }
+                drawing_area.queue_draw();
+                return Inhibit(true);
+            }
+            _ => return Inhibit(false),
+        };
+
+        let cursor = match self.board_state.cursor() {
+            Some(cursor) => {
+                let file = (i8::from(cursor.file()) + df).max(0).min(7);
+                let rank = (i8::from(cursor.rank()) + dr).max(0).min(7);
+                Square::from_coords(File::new(file as u32), Rank::new(rank as u32))
+            }
+            None => self.pieces.selected().unwrap_or_else(|| {
+                // start in the viewer's bottom left corner
+                let file = if right > 0 { File::A } else { File::H };
+                Square::from_coords(file, self.board_state.orientation().fold_wb(Rank::First, Rank::Eighth))
+            }),
+        };
+
+        self.board_state.set_cursor(Some(cursor));
+        drawing_area.queue_draw();
+        Inhibit(true)
     }
 
     fn button_press_event(&mut self, stream: &Stream, drawing_area: &DrawingArea, e: &EventButton) {
+        // any click cancels a queued premove
+        if self.board_state.premovable() && self.board_state.premove().is_some() {
+            self.board_state.set_premove(None);
+            stream.emit(GroundMsg::PremoveCancelled);
+            drawing_area.queue_draw();
+        }
+
+        // a tap on the destination confirms a previewed move, anything
+        // else cancels it
+        if let Some((orig, dest)) = self.board_state.pending_move() {
+            let square = EventContext::new(&self.board_state, stream, drawing_area, e.position()).square();
+            let landing = self.board_state.landing_square(orig, dest);
+            self.board_state.set_pending_move(None);
+            drawing_area.queue_draw();
+
+            if e.button() == 1 && (square == Some(dest) || square == Some(landing)) {
+                stream.emit(GroundMsg::UserMove(orig, dest, None));
+            }
+            return;
+        }
+
         let ctx = EventContext::new(&self.board_state, stream, drawing_area, e.position());
+        if self.board_state.view_only() {
+            self.drawable.mouse_down(&ctx, e);
+            return;
+        }
+
+        let selected = self.pieces.selected();
         let promotable = &mut self.promotable;
         let pieces = &mut self.pieces;
 
         if let Inhibit(false) = promotable.mouse_down(pieces, &ctx) {
-            pieces.selection_mouse_down(&ctx, e);
-            pieces.drag_mouse_down(&ctx, e);
+            if let Inhibit(false) = pieces.selection_mouse_down(&ctx, e) {
+                pieces.drag_mouse_down(&ctx, e);
+            }
             self.drawable.mouse_down(&ctx, e);
         }
+
+        if pieces.selected().is_some() && self.board_state.all_targets().is_some() {
+            self.board_state.set_all_targets(None);
+            drawing_area.queue_draw();
+        }
+
+        self.emit_selection_changed(stream, selected);
     }
 }
 
+/// Request the size of a board with fixed square size, or let the board
+/// scale with the widget.
+fn request_size(drawing_area: &DrawingArea, board_state: &BoardState) {
+    let (width, height) = fixed_size(board_state).unwrap_or((-1, -1));
+    drawing_area.set_size_request(width, height);
+}
+
+/// Motion events with or without a button held.
+fn motion_mask(hover_events: bool) -> EventMask {
+    if hover_events {
+        EventMask::POINTER_MOTION_MASK
+    } else {
+        EventMask::BUTTON_MOTION_MASK
+    }
+}
+
+fn render(cr: &Context, board_state: &BoardState, pieces: &Pieces, promotable: &Promotable, drawable: &Drawable) -> Result<(), cairo::Error> {
+    board_state.draw(cr, pieces.selected(), pieces.occupied())?;
+    pieces.draw(cr, board_state, promotable)?;
+    board_state.draw_spotlight(cr)?;
+    drawable.draw(cr, board_state)?;
+    pieces.draw_drag(cr, board_state)?;
+    promotable.draw(cr, board_state)?;
+    Ok(())
+}
+
+fn render_frame<P: Position>(board_state: &mut BoardState, pos: &P, pieces: &Pieces, size: i32) -> Result<ImageSurface, cairo::Error> {
+    board_state.set_check(if pos.checkers().any() { pos.board().king_of(pos.turn()) } else { None });
+    board_state.set_turn(Some(pos.turn()));
+
+    let surface = ImageSurface::create(Format::ARgb32, size, size)?;
+    {
+        let cr = Context::new(&surface)?;
+        cr.set_matrix(compute_matrix(board_state, 0, 0, size, size));
+        render(&cr, board_state, pieces, &Promotable::new(), &Drawable::new())?;
+    }
+    Ok(surface)
+}
+
 pub(crate) struct WidgetContext<'a> {
     matrix: Matrix,
     drawing_area: &'a DrawingArea,
@@ -374,16 +1574,7 @@ impl<'a> WidgetContext<'a> {
     fn new(board_state: &'a BoardState, drawing_area: &'a DrawingArea) -> WidgetContext<'a>
     {
         let alloc = drawing_area.allocation();
-        let size = max(min(alloc.width(), alloc.height()), 9);
-
-        let mut matrix = Matrix::identity();
-        matrix.translate(f64::from(alloc.x()), f64::from(alloc.y()));
-
-        matrix.translate(f64::from(alloc.width()) / 2.0, f64::from(alloc.height()) / 2.0);
-        matrix.scale(f64::from(size) / 9.0, f64::from(size) / 9.0);
-        matrix.rotate(board_state.orientation().fold_wb(0.0, PI));
-        matrix.translate(-4.0, -4.0);
-
+        let matrix = compute_matrix(board_state, alloc.x(), alloc.y(), alloc.width(), alloc.height());
         WidgetContext { matrix, drawing_area }
     }
 
@@ -402,30 +1593,20 @@ impl<'a> WidgetContext<'a> {
     }
 
     pub fn queue_draw_square(&self, square: Square) {
-        self.queue_draw_rect(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+        let (x, y, width, height) = square_area(square);
+        self.queue_draw_rect(x, y, width, height);
     }
 
     pub fn queue_draw_rect(&self, x: f64, y: f64, width: f64, height: f64) {
-        // round to square grid
-        let (rx, ry) = (x.floor(), y.floor());
-
-        // transform to widget coordinates
-        let matrix = self.matrix();
-        let (x1, y1) = matrix.transform_point(rx, ry);
-        let (x2, y2) = matrix.transform_point((x + width).ceil(), (y + height).ceil());
-
-        let xmin = min(x1.floor() as i32, x2.floor() as i32);
-        let ymin = min(y1.floor() as i32, y2.floor() as i32);
-        let xmax = max(x1.ceil() as i32, x2.ceil() as i32);
-        let ymax = max(y1.ceil() as i32, y2.ceil() as i32);
-
+        let (x, y, width, height) = damage_rect(&self.matrix(), x, y, width, height);
         let alloc = self.drawing_area.allocation();
-        self.drawing_area.queue_draw_area(xmin - alloc.x(), ymin - alloc.y(), xmax - xmin, ymax - ymin);
+        self.drawing_area.queue_draw_area(x - alloc.x(), y - alloc.y(), width, height);
     }
 }
 
 pub(crate) struct EventContext<'a> {
     widget: WidgetContext<'a>,
+    board_state: &'a BoardState,
     stream: &'a Stream,
     pos: (f64, f64),
     square: Option<Square>,
@@ -445,6 +1626,7 @@ impl<'a> EventContext<'a> {
 
         EventContext {
             widget,
+            board_state,
             stream,
             pos,
             square,
@@ -455,6 +1637,10 @@ impl<'a> EventContext<'a> {
         &self.widget
     }
 
+    pub fn board_state(&self) -> &'a BoardState {
+        self.board_state
+    }
+
     pub fn stream(&self) -> &'a Stream {
         self.stream
     }
@@ -467,3 +1653,65 @@ impl<'a> EventContext<'a> {
         self.square
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+
+    /// Size of test renders, a multiple of 9 for whole pixel squares.
+    const RENDER_SIZE: i32 = 360;
+
+    /// Hash the colors of the pixels, independent of the stride and of
+    /// whether the surface has an alpha channel.
+    fn pixel_hash(surface: &mut ImageSurface) -> u64 {
+        let (width, height, stride) = (surface.width() as usize, surface.height() as usize, surface.stride() as usize);
+        let opaque = surface.format() == Format::Rgb24;
+        let data = surface.data().expect("exclusive image surface");
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write_usize(width);
+        hasher.write_usize(height);
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * stride + 4 * x;
+                let pixel = u32::from_ne_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+                hasher.write_u32(if opaque { pixel | 0xff00_0000 } else { pixel });
+            }
+        }
+        hasher.finish()
+    }
+
+    fn render_hash(board_state: &mut BoardState, pos: &Chess, pieces: &Pieces) -> u64 {
+        // highlights fade in with the clock
+        board_state.set_animate_highlights(false);
+        pixel_hash(&mut render_frame(board_state, pos, pieces, RENDER_SIZE).expect("render frame"))
+    }
+
+    #[test]
+    fn test_render_deterministic() {
+        let pos = Chess::default();
+        let pieces = Pieces::new_from_board(pos.board());
+        let first = render_hash(&mut BoardState::from_position(&pos), &pos, &pieces);
+        let second = render_hash(&mut BoardState::from_position(&pos), &pos, &pieces);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_render_check_and_hints() {
+        let fen: Fen = "rnbqkbnr/ppppp1pp/5p2/7Q/4P3/8/PPPP1PPP/RNB1KBNR b KQkq - 1 2".parse().expect("valid fen");
+        let pos: Chess = fen.position(CastlingMode::Standard).expect("legal position");
+        let mut board_state = BoardState::from_position(&pos);
+        let mut pieces = Pieces::new_from_board(pos.board());
+        let plain = render_hash(&mut board_state, &pos, &pieces);
+
+        board_state.set_last_move(Some((Square::D1, Square::H5)));
+        let last_move = render_hash(&mut board_state, &pos, &pieces);
+        assert_ne!(plain, last_move);
+
+        assert!(pieces.select(&board_state, Square::G7));
+        assert_ne!(last_move, render_hash(&mut board_state, &pos, &pieces));
+    }
+}
@@ -36,8 +36,13 @@ mod pieceset;
 mod pieces;
 mod promotable;
 mod drawable;
+mod theme;
 mod util;
 
-pub use ground::{Ground, GroundMsg, Pos};
+pub use ground::{Ground, GroundInit, GroundMsg, Pos};
 pub use GroundMsg::*;
 pub use drawable::{DrawBrush, DrawShape};
+pub use pieces::{Pieces, BoardDiff};
+pub use pieceset::{PieceSet, PieceSetError};
+pub use boardstate::{SelectionStyle, PromotionLayout, HighlightOverlap, SquarePainter, CoordinateMode, RenderQuality, Easing};
+pub use theme::BoardTheme;
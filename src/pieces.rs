@@ -18,32 +18,73 @@ use std::f64::consts::PI;
 
 use time::SteadyTime;
 
+use gtk::prelude::*;
 use gdk::EventButton;
 use cairo::Context;
-use rsvg::HandleExt;
 
-use shakmaty::{Square, Piece, Bitboard, Board};
+use shakmaty::{Square, Rank, Color, Role, Piece, Bitboard, Board};
+use shakmaty::fen::ParseFenError;
 
-use util::{ease, file_to_float, pos_to_square, rank_to_float, square_to_pos};
+use util::{ease, file_to_float, pos_to_square, premove_targets, rank_to_float, square_to_pos};
 use promotable::Promotable;
-use boardstate::BoardState;
-use ground::{GroundMsg, EventContext, WidgetContext};
+use boardstate::{BoardState, SelectionStyle, Easing, HIGHLIGHT_FADE_MS};
+use ground::{GroundMsg, EventContext, WidgetContext, Stream};
 
+/// The pieces on the board, with their animations, the selection and the
+/// dragged piece.
+#[derive(Debug)]
 pub struct Pieces {
     figurines: Vec<Figurine>,
-    selected: Option<Square>,
+    selected: Selection,
     drag: Option<Drag>,
     past: SteadyTime,
+    paused: Option<SteadyTime>,
+    scrubbing: bool,
+    pondering: Option<(Square, Piece)>,
+    slide_easing: Easing,
+    fade_easing: Easing,
+    animation_duration: f64,
 }
 
+#[derive(Debug)]
+struct Selection {
+    square: Option<Square>,
+    previous: Option<Square>,
+    since: SteadyTime,
+    pondering: bool,
+}
+
+/// The effect of a click on the selection.
+enum Click {
+    /// The button does not change the selection.
+    Ignored,
+    /// The selection changed.
+    Selected,
+    /// The click moves the selected piece.
+    Move(Square, Square),
+}
+
+#[derive(Debug)]
 struct Drag {
     square: Square,
     piece: Piece,
     start: (f64, f64),
     pos: (f64, f64),
     threshold: bool,
+    since: SteadyTime,
 }
 
+/// The effect of `Pieces::set_board`.
+#[derive(Default, Debug)]
+pub struct BoardDiff {
+    /// Squares that pieces moved from or to, appeared on or vanished from.
+    pub changed: Bitboard,
+    /// Squares of pieces that were captured, i.e. removed while pieces of
+    /// the other color moved or were added.
+    pub captured: Bitboard,
+}
+
+#[derive(Debug)]
 pub struct Figurine {
     square: Square,
     piece: Piece,
@@ -53,21 +94,30 @@ pub struct Figurine {
     last_drag: SteadyTime,
     fading: bool,
     replaced: bool,
+    promoted: bool,
+    appearing: bool,
     dragging: bool,
 }
 
 impl Pieces {
-    pub fn new() -> Pieces {
-        Pieces::new_from_board(&Board::new())
-    }
-
     pub fn new_from_board(board: &Board) -> Pieces {
         let now = SteadyTime::now();
 
         Pieces {
-            selected: None,
+            selected: Selection {
+                square: None,
+                previous: None,
+                since: now,
+                pondering: false,
+            },
             drag: None,
             past: now,
+            paused: None,
+            scrubbing: false,
+            pondering: None,
+            slide_easing: Easing::InOutCubic,
+            fade_easing: Easing::InOutCubic,
+            animation_duration: 0.3,
             figurines: board.clone().into_iter().map(|(square, piece)| Figurine {
                 square,
                 piece,
@@ -77,21 +127,69 @@ impl Pieces {
                 last_drag: now,
                 fading: false,
                 replaced: false,
+                promoted: false,
+                appearing: false,
                 dragging: false,
             }).collect(),
         }
     }
 
-    pub fn set_board(&mut self, board: &Board) {
+    /// Set up pieces from the piece placement part of a FEN, like
+    /// `rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR`.
+    pub fn from_fen(board_fen: &str) -> Result<Pieces, ParseFenError> {
+        board_fen.parse().map(|board| Pieces::new_from_board(&board))
+    }
+
+    /// Show the pieces part way (`t` from 0.0 to 1.0) between two boards,
+    /// on straight lines at constant speed, e.g. for a replay slider. The
+    /// clock does not advance them until the next board is set.
+    pub fn interpolate(&mut self, from: &Board, to: &Board, t: f64) {
+        // restart from the first board, keeping the settings, the
+        // selection and the dragged piece
+        self.figurines = Pieces::new_from_board(from).figurines;
+        if let Some((square, piece)) = self.drag.as_ref().map(|d| (d.square, d.piece)) {
+            match self.figurine_at_mut(square).filter(|f| f.piece == piece) {
+                Some(figurine) => figurine.dragging = true,
+                None => self.drag = None,
+            }
+        }
+
+        self.set_board(to);
+        self.set_animation_progress(t);
+        self.scrubbing = true;
+    }
+
+    /// Animate the pieces to a new board. Returns the squares that
+    /// changed.
+    pub fn set_board(&mut self, board: &Board) -> BoardDiff {
+        // nothing to diff
+        if self.shows_board(board) {
+            return BoardDiff::default();
+        }
+
+        let slide_easing = self.slide_easing();
+        self.scrubbing = false;
+
+        // with a duration of 0 pieces snap into place, without any frames
+        // at their start positions
+        let elapsed = if self.animation_duration > 0.0 { 0.0 } else { 1.0 };
+
         // clean faded figurines
         let now = SteadyTime::now();
-        self.figurines.retain(|f| !f.fading || f.alpha() > 0.0001);
+        let fade_easing = self.fade_easing;
+        self.figurines.retain(|f| !f.fading || f.alpha(fade_easing) > 0.0001);
 
         // diff
         let mut added: Vec<_> = board.clone().into_iter().filter(|&(sq, piece)| {
             self.figurine_at(sq).map_or(true, |f| f.piece != piece)
         }).collect();
 
+        let mut changed: Bitboard = added.iter().map(|&(sq, _)| sq).collect();
+        let mut removed_pawns = Vec::new();
+        let mut vacated = Bitboard(0);
+        let mut removed = Vec::new();
+        let mut active: Vec<Color> = added.iter().map(|&(_, piece)| piece.color).collect();
+
         for figurine in &mut self.figurines {
             if figurine.fading {
                 continue;
@@ -100,10 +198,14 @@ impl Pieces {
             // figurine was removed from the square
             if !board.by_piece(figurine.piece).contains(figurine.square) {
                 // checkpoint animation
-                figurine.start = figurine.pos();
-                figurine.elapsed = 0.0;
+                figurine.start = figurine.pos(slide_easing);
+                figurine.elapsed = elapsed;
                 figurine.time = now;
 
+                // only grow or fade into place once
+                figurine.promoted = false;
+                figurine.appearing = false;
+
                 // cancel drag
                 if figurine.dragging {
                     figurine.dragging = false;
@@ -118,6 +220,9 @@ impl Pieces {
 
                 if let Some(best) = best {
                     // found a close square it could have moved to
+                    vacated.add(figurine.square);
+                    changed.add(figurine.square);
+                    active.push(figurine.piece.color);
                     figurine.square = best;
                     added.retain(|&(sq, _)| sq != best);
 
@@ -127,32 +232,135 @@ impl Pieces {
                     }
                 } else {
                     // fade it out
+                    changed.add(figurine.square);
+                    removed.push((figurine.piece.color, figurine.square));
                     figurine.fading = true;
                     figurine.replaced = board.occupied().contains(figurine.square);
+
+                    if figurine.piece.role == Role::Pawn {
+                        let color = figurine.piece.color;
+                        removed_pawns.push((color, color.fold_wb(figurine.square, figurine.square.flip_vertical())));
+                    }
                 }
             }
         }
 
         // add new figurines
         for (square, piece) in added {
+            // a piece on the back rank that replaces a pawn next to it
+            let relative = piece.color.fold_wb(square, square.flip_vertical());
+            let promoted = piece.role != Role::Pawn && relative.rank() == Rank::Eighth && removed_pawns.iter().any(|&(color, pawn)| {
+                color == piece.color && pawn.rank() == Rank::Seventh && pawn.distance(relative) == 1
+            });
+
+            // a piece that reappears where another one moved away from,
+            // e.g. a captured piece on takeback
+            let appearing = !promoted && vacated.contains(square);
+
             self.figurines.push(Figurine {
                 square,
                 piece,
                 start: (0.5 + file_to_float(square.file()), 7.5 - rank_to_float(square.rank())),
-                elapsed: 0.0,
+                elapsed,
                 time: now,
                 last_drag: self.past,
                 fading: false,
                 replaced: false,
+                promoted,
+                appearing,
                 dragging: false,
             });
         }
+
+        let captured = removed.into_iter()
+            .filter(|&(color, _)| !active.contains(&color))
+            .map(|(_, square)| square)
+            .collect();
+
+        BoardDiff { changed, captured }
+    }
+
+    /// Check if `board` is already shown, so that setting it again would
+    /// change nothing.
+    pub fn shows_board(&self, board: &Board) -> bool {
+        !self.scrubbing && self.board() == *board
+    }
+
+    /// Drive running animations to `t` (from 0.0 to 1.0), independent of
+    /// the clock.
+    pub fn set_animation_progress(&mut self, t: f64) {
+        for figurine in &mut self.figurines {
+            if figurine.elapsed < 1.0 {
+                figurine.elapsed = t;
+            }
+        }
+    }
+
+    /// Easing of piece movements. While scrubbing, pieces move linearly
+    /// with the slider.
+    fn slide_easing(&self) -> Easing {
+        if self.scrubbing { Easing::Linear } else { self.slide_easing }
+    }
+
+    pub fn set_slide_easing(&mut self, slide_easing: Easing) {
+        self.slide_easing = slide_easing;
+    }
+
+    pub fn set_fade_easing(&mut self, fade_easing: Easing) {
+        self.fade_easing = fade_easing;
+    }
+
+    /// Set the duration of piece animations in seconds. `0.0` moves
+    /// pieces instantly.
+    pub fn set_animation_duration(&mut self, animation_duration: f64) {
+        self.animation_duration = animation_duration.max(0.0);
+    }
+
+    pub fn animation_duration(&self) -> f64 {
+        self.animation_duration
+    }
+
+    /// Hold running animations at their current progress.
+    pub fn pause_animations(&mut self) {
+        if self.paused.is_none() {
+            self.paused = Some(SteadyTime::now());
+        }
+    }
+
+    /// Continue paused animations where they were held.
+    pub fn resume_animations(&mut self) {
+        if let Some(paused) = self.paused.take() {
+            let offset = SteadyTime::now() - paused;
+            for figurine in &mut self.figurines {
+                if figurine.elapsed < 1.0 {
+                    figurine.time = figurine.time + offset;
+                }
+            }
+        }
+    }
+
+    pub fn finish_animations(&mut self) {
+        self.figurines.retain(|f| !f.fading);
+
+        for figurine in &mut self.figurines {
+            figurine.start = square_to_pos(figurine.square);
+            figurine.elapsed = 1.0;
+        }
     }
 
     pub fn occupied(&self) -> Bitboard {
         self.figurines.iter().filter(|f| !f.fading).map(|f| f.square).collect()
     }
 
+    /// The board with the pieces currently shown.
+    pub fn board(&self) -> Board {
+        let mut board = Board::empty();
+        for figurine in self.figurines.iter().filter(|f| !f.fading) {
+            board.set_piece_at(figurine.square, figurine.piece);
+        }
+        board
+    }
+
     pub fn figurine_at(&self, square: Square) -> Option<&Figurine> {
         self.figurines.iter().find(|f| !f.fading && f.square == square)
     }
@@ -161,31 +369,120 @@ impl Pieces {
         self.figurines.iter_mut().find(|f| !f.fading && f.square == square)
     }
 
+    pub fn selected(&self) -> Option<Square> {
+        self.selected.square
+    }
+
+    /// Select the piece on `square`, as if the user clicked it. Returns
+    /// `false` if there is no movable piece on the square.
+    pub fn select(&mut self, state: &BoardState, square: Square) -> bool {
+        let movable = self.occupied() & !state.immovable();
+        if movable.contains(square) {
+            self.selected.set(Some(square));
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selected.set(None);
+    }
+
+    /// Drop a dragged piece back on its square. Returns the square if the
+    /// drag had started.
+    pub fn cancel_drag(&mut self) -> Option<Square> {
+        let drag = self.drag.take()?;
+
+        if let Some(figurine) = self.dragging_mut() {
+            figurine.dragging = false;
+        }
+
+        Some(drag.square).filter(|_| drag.threshold)
+    }
+
+    pub fn dragging(&self) -> Option<(Square, (f64, f64))> {
+        self.drag.as_ref().map(|d| (d.square, d.pos))
+    }
+
     pub fn dragging_mut(&mut self) -> Option<&mut Figurine> {
         self.figurines.iter_mut().find(|f| f.dragging)
     }
 
-    pub(crate) fn selection_mouse_down(&mut self, ctx: &EventContext, e: &EventButton) {
-        let orig = self.selected.take();
+    pub(crate) fn selection_mouse_down(&mut self, ctx: &EventContext, e: &EventButton) -> Inhibit {
+        match self.click(ctx.board_state(), e.button(), ctx.square()) {
+            Click::Ignored => Inhibit(false),
+            Click::Selected => {
+                ctx.widget().queue_draw();
+                Inhibit(false)
+            }
+            Click::Move(orig, dest) => {
+                ctx.widget().queue_draw();
+                self.complete_move(ctx.board_state(), ctx.stream(), orig, dest);
+                Inhibit(true)
+            }
+        }
+    }
 
-        if e.button() == 1 {
-            let dest = ctx.square();
-            self.selected = dest.filter(|sq| self.occupied().contains(*sq));
+    /// Update the selection for a click with `button` on `square`.
+    fn click(&mut self, state: &BoardState, button: u32, square: Option<Square>) -> Click {
+        match button {
+            1 | 3 => (),
+            2 if state.middle_click_deselects() => (),
+            _ => return Click::Ignored,
+        }
 
-            if let (Some(orig), Some(dest)) = (orig, dest) {
-                self.selected = None;
-                if orig != dest {
-                    ctx.stream().emit(GroundMsg::UserMove(orig, dest, None));
+        // a piece kept selected after its move cannot move again
+        let orig = self.selected.square.filter(|_| !self.selected.pondering);
+        self.selected.set(None);
+        self.pondering = None;
+
+        if button == 1 {
+            if let (Some(orig), Some(dest)) = (orig, square) {
+                if orig != dest && self.switches_selection(state, orig, dest) {
+                    self.selected.set(Some(dest));
+                } else if orig != dest {
+                    // complete the move without picking up the piece on the
+                    // destination square, so that its hints do not flash
+                    return Click::Move(orig, dest);
                 }
+            } else {
+                let movable = self.occupied() & !state.immovable();
+                self.selected.set(square.filter(|sq| movable.contains(*sq)));
             }
         }
 
-        ctx.widget().queue_draw();
+        Click::Selected
+    }
+
+    /// Check if clicking `dest` with `orig` selected should select the
+    /// friendly piece on `dest` rather than attempting a move.
+    fn switches_selection(&self, state: &BoardState, orig: Square, dest: Square) -> bool {
+        let friendly = match (self.figurine_at(orig), self.figurine_at(dest)) {
+            (Some(a), Some(b)) => a.piece.color == b.piece.color,
+            _ => false,
+        };
+
+        friendly && state.switch_selection() && !state.immovable().contains(dest) &&
+            !state.valid_move(orig, dest) && state.move_targets(dest).any()
     }
 
     pub(crate) fn drag_mouse_down(&mut self, ctx: &EventContext, e: &EventButton) {
-        if e.button() == 1 {
-            if let Some(square) = ctx.square() {
+        self.start_drag(ctx.board_state(), e.button(), ctx.square(), ctx.pos());
+    }
+
+    /// Pick up the piece on `square` if `button` drags pieces.
+    fn start_drag(&mut self, state: &BoardState, button: u32, square: Option<Square>, pos: (f64, f64)) {
+        if button == 1 {
+            if let Some(square) = square {
+                if state.immovable().contains(square) {
+                    return;
+                }
+
+                if state.drag_legal_only() && !state.move_targets(square).any() {
+                    return;
+                }
+
                 let piece = if let Some(figurine) = self.figurine_at_mut(square) {
                     figurine.dragging = true;
                     figurine.piece
@@ -196,9 +493,10 @@ impl Pieces {
                 self.drag = Some(Drag {
                     square,
                     piece,
-                    start: ctx.pos(),
-                    pos: ctx.pos(),
+                    start: pos,
+                    pos,
                     threshold: false,
+                    since: SteadyTime::now(),
                 });
             }
         }
@@ -206,24 +504,33 @@ impl Pieces {
 
     pub(crate) fn drag_mouse_move(&mut self, ctx: &EventContext) {
         if let Some(ref mut drag) = self.drag {
-            ctx.widget().queue_draw_rect(drag.pos.0 - 0.5, drag.pos.1 - 0.5, 1.0, 1.0);
+            // leave room for the coordinate tooltip above or below the piece
+            let margin = if ctx.board_state().drag_coordinate_tooltip() { 0.4 } else { 0.0 };
+
+            ctx.widget().queue_draw_rect(drag.pos.0 - 0.5, drag.pos.1 - 0.5 - margin, 1.0, 1.0 + 2.0 * margin);
             if let Some(sq) = pos_to_square(drag.pos) {
                 ctx.widget().queue_draw_square(sq);
             }
             drag.pos = ctx.pos();
-            ctx.widget().queue_draw_rect(drag.pos.0 - 0.5, drag.pos.1 - 0.5, 1.0, 1.0);
+            ctx.widget().queue_draw_rect(drag.pos.0 - 0.5, drag.pos.1 - 0.5 - margin, 1.0, 1.0 + 2.0 * margin);
             if let Some(sq) = pos_to_square(drag.pos) {
                 ctx.widget().queue_draw_square(sq);
             }
 
             let (dx, dy) = (drag.start.0 - drag.pos.0, drag.start.1 - drag.pos.1);
             let (pdx, pdy) = ctx.widget().matrix().transform_distance(dx, dy);
-            drag.threshold |= dx.hypot(dy) >= 0.1 || pdx.hypot(pdy) >= 4.0;
+            let held = (SteadyTime::now() - drag.since).num_milliseconds() >= ctx.board_state().drag_delay_ms();
+            if !drag.threshold && held && (dx.hypot(dy) >= 0.1 || pdx.hypot(pdy) >= 4.0) {
+                drag.threshold = true;
+                ctx.stream().emit(GroundMsg::DragStarted(drag.square));
+            }
 
             if drag.threshold {
-                // ensure orig square is selected
-                if self.selected != Some(drag.square) {
-                  self.selected = Some(drag.square);
+                // ensure orig square is selected, or nothing if move hints
+                // should not show while dragging
+                let selected = Some(drag.square).filter(|_| ctx.board_state().select_on_drag());
+                if self.selected.square != selected {
+                  self.selected.set(selected);
                   ctx.widget().queue_draw();
                 } else {
                   ctx.widget().queue_draw_square(drag.square);
@@ -241,31 +548,130 @@ impl Pieces {
                 figurine.dragging = false;
             }
 
+            if drag.threshold {
+                ctx.stream().emit(GroundMsg::DragEnded(drag.square, ctx.square()));
+            }
+
             let dest = ctx.square().unwrap_or(drag.square);
 
             if drag.square != dest {
                 (drag.square, dest)
             } else {
+                if drag.threshold && ctx.board_state().allow_null_drop() {
+                    self.selected.set(None);
+                    ctx.stream().emit(GroundMsg::UserMove(dest, dest, None));
+                }
                 return;
             }
         } else {
             return;
         };
 
-        self.selected = None;
+        self.selected.set(None);
 
         if orig != dest {
-            ctx.stream().emit(GroundMsg::UserMove(orig, dest, None));
+            self.complete_move(ctx.board_state(), ctx.stream(), orig, dest);
+        }
+    }
+
+    /// Send the move completed by the user, or preview it if moves need
+    /// to be confirmed.
+    fn complete_move(&mut self, state: &BoardState, stream: &Stream, orig: Square, dest: Square) {
+        stream.emit(self.move_msg(state, orig, dest));
+    }
+
+    /// The message for a move of the user from `orig` to `dest`.
+    fn move_msg(&mut self, state: &BoardState, orig: Square, dest: Square) -> GroundMsg {
+        if self.is_premove(state, orig, dest) {
+            return GroundMsg::PremoveSet(orig, dest);
+        }
+
+        self.start_pondering(state, orig, dest);
+        let dest = state.normalize_dest(orig, dest);
+        if state.confirm_moves() {
+            GroundMsg::MovePreview(orig, dest)
+        } else {
+            GroundMsg::UserMove(orig, dest, None)
+        }
+    }
+
+    /// Act on the square of the keyboard cursor like a click: select the
+    /// piece on it, or move the selected piece there.
+    pub(crate) fn key_select(&mut self, state: &BoardState, stream: &Stream, square: Square) {
+        let orig = self.selected.square.filter(|_| !self.selected.pondering);
+        self.selected.set(None);
+        self.pondering = None;
+
+        match orig {
+            Some(orig) if orig != square && self.switches_selection(state, orig, square) => {
+                self.selected.set(Some(square));
+            }
+            Some(orig) if orig != square => self.complete_move(state, stream, orig, square),
+            Some(_) => (),
+            None => {
+                let movable = self.occupied() & !state.immovable();
+                self.selected.set(Some(square).filter(|sq| movable.contains(*sq)));
+            }
+        }
+    }
+
+    /// Check if moving `orig` to `dest` queues a premove, because the
+    /// piece belongs to the side not to move.
+    fn is_premove(&self, state: &BoardState, orig: Square, dest: Square) -> bool {
+        match (state.turn(), self.figurine_at(orig)) {
+            (Some(turn), Some(figurine)) if state.premovable() && figurine.piece.color != turn => {
+                premove_targets(&self.board(), orig).contains(dest)
+            }
+            _ => false,
+        }
+    }
+
+    /// Remember to select the moved piece on `dest` once the move is
+    /// played, if hints should persist after moving.
+    fn start_pondering(&mut self, state: &BoardState, orig: Square, dest: Square) {
+        self.pondering = self.figurine_at(orig)
+            .filter(|_| state.persist_hints_after_move())
+            .map(|f| (dest, f.piece));
+    }
+
+    /// Targets of the piece kept selected after its move. Legal moves are
+    /// only known for the side to move, so show the squares it attacks.
+    fn ponder_targets(&self, square: Square) -> Bitboard {
+        let board = self.board();
+        match board.color_at(square) {
+            Some(color) => board.attacks_from(square) & !board.by_color(color),
+            None => Bitboard(0),
         }
     }
 
     pub(crate) fn queue_animation(&mut self, ctx: &WidgetContext) {
-        for figurine in &mut self.figurines {
-            figurine.queue_animation(ctx);
+        if self.paused.is_none() && !self.scrubbing {
+            for figurine in &mut self.figurines {
+                figurine.queue_animation(ctx, self.slide_easing, self.animation_duration);
+            }
+        }
+
+        // select the moved piece after its animation
+        if let Some((square, piece)) = self.pondering {
+            let arrived = self.figurine_at(square).map_or(false, |f| f.piece == piece && f.elapsed >= 1.0);
+            if arrived {
+                self.pondering = None;
+                self.selected.set(Some(square));
+                self.selected.pondering = true;
+                ctx.queue_draw();
+            }
+        }
+
+        // highlight fade
+        if (SteadyTime::now() - self.selected.since).num_milliseconds() < 2 * HIGHLIGHT_FADE_MS {
+            for square in self.selected.square.iter().chain(self.selected.previous.iter()) {
+                ctx.queue_draw_square(*square);
+            }
         }
     }
 
     pub(crate) fn draw(&self, cr: &Context, state: &BoardState, promotable: &Promotable) -> Result<(), cairo::Error> {
+        self.draw_all_targets(cr, state)?;
         self.draw_selection(cr, state)?;
         self.draw_move_hints(cr, state)?;
 
@@ -287,9 +693,57 @@ impl Pieces {
             }
         }
 
+        self.draw_pending_move(cr, state)?;
+
+        Ok(())
+    }
+
+    /// Draw the pieces at rest on their squares, without hints, previews or
+    /// hover effects.
+    pub(crate) fn draw_plain(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
+        for figurine in self.figurines.iter().filter(|f| !f.fading) {
+            cr.save()?;
+            let (x, y) = square_to_pos(figurine.square);
+            cr.translate(x, y);
+            state.upright(cr);
+            cr.translate(-0.5, -0.5);
+            state.piece_set().render(cr, &figurine.piece, 1.0)?;
+            cr.restore()?;
+        }
+
         Ok(())
     }
 
+    /// Draw the move awaiting confirmation as a translucent piece on its
+    /// destination, framed to invite a second tap.
+    fn draw_pending_move(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
+        let (orig, dest) = match state.pending_move() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let piece = match self.figurine_at(orig) {
+            Some(figurine) => figurine.piece,
+            None => return Ok(()),
+        };
+
+        let square = state.landing_square(orig, dest);
+        let (x, y) = (file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+
+        let (r, g, b, a) = state.theme().selected;
+        cr.set_source_rgba(r, g, b, a);
+        cr.set_line_width(0.08);
+        cr.rectangle(x + 0.04, y + 0.04, 0.92, 0.92);
+        cr.stroke()?;
+
+        cr.save()?;
+        cr.translate(x + 0.5, y + 0.5);
+        state.upright(cr);
+        cr.translate(-0.5, -0.5);
+        state.piece_set().render(cr, &piece, 0.5 * state.piece_opacity())?;
+        cr.restore()
+    }
+
     fn draw_figurine(&self, cr: &Context, figurine: &Figurine, state: &BoardState, promotable: &Promotable) -> Result<(), cairo::Error> {
         // hide piece while promotion dialog is open
         if promotable.is_promoting(figurine.square) {
@@ -301,33 +755,54 @@ impl Pieces {
             figurine.dragging &&
             self.drag.as_ref().map_or(false, |d| d.threshold && d.square == figurine.square);
 
-        cr.push_group();
-
-        let (x, y) = figurine.pos();
-        cr.translate(x, y);
-        cr.rotate(state.orientation().fold_wb(0.0, PI));
-        cr.translate(-0.5, -0.5);
-        cr.scale(state.piece_set().scale(), state.piece_set().scale());
-
-        state.piece_set().by_piece(&figurine.piece).render_cairo(cr);
+        // let promoted pieces grow into place
+        let grow = if figurine.promoted && state.animate_promotions() {
+            figurine.elapsed
+        } else {
+            1.0
+        };
 
-        cr.pop_group_to_source()?;
+        // let the square shine through the hovered piece
+        let hover_fade = match state.hover_piece_fade() {
+            Some(fade) if !figurine.fading && state.hovered() == Some(figurine.square) => fade,
+            _ => 1.0,
+        };
 
-        cr.paint_with_alpha(if dragging { 0.2 } else { figurine.alpha() })?;
+        let alpha = if dragging { 0.2 } else { figurine.alpha(self.fade_easing) * ease(0.0, 1.0, grow) * hover_fade };
 
-        Ok(())
+        cr.save()?;
+        let (x, y) = figurine.pos(self.slide_easing());
+        cr.translate(x, y);
+        state.upright(cr);
+        cr.scale(ease(0.2, 1.0, grow), ease(0.2, 1.0, grow));
+        cr.translate(-0.5, -0.5);
+        state.piece_set().render(cr, &figurine.piece, alpha * state.piece_opacity())?;
+        cr.restore()
     }
 
     fn draw_selection(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
-        if let Some(selected) = self.selected {
-            cr.rectangle(file_to_float(selected.file()), 7.0 - rank_to_float(selected.rank()), 1.0, 1.0);
-            cr.set_source_rgba(0.08, 0.47, 0.11, 0.5);
-            cr.fill()?;
+        let fade = state.highlight_fade(self.selected.since);
+        let (r, g, b, a) = state.theme().selected;
 
+        if let Some(previous) = self.selected.previous.filter(|_| fade < 1.0) {
+            if !state.hides_selection(previous) {
+                cr.set_source_rgba(r, g, b, a * (1.0 - fade));
+                self.draw_selected_square(cr, state, previous)?;
+            }
+        }
+
+        if let Some(selected) = self.selected.square {
+            if !state.hides_selection(selected) {
+                cr.set_source_rgba(r, g, b, a * fade);
+                self.draw_selected_square(cr, state, selected)?;
+            }
+
+            // tint the move target under the dragged piece, which is a
+            // move hint as well
             if let Some(hovered) = self.drag.as_ref().and_then(|d| pos_to_square(d.pos)) {
-                if state.valid_move(selected, hovered) {
+                if state.show_move_hints() && state.valid_move(selected, hovered) {
                     cr.rectangle(file_to_float(hovered.file()), 7.0 - rank_to_float(hovered.rank()), 1.0, 1.0);
-                    cr.set_source_rgba(0.08, 0.47, 0.11, 0.25);
+                    cr.set_source_rgba(r, g, b, 0.5 * a);
                     cr.fill()?;
                 }
             }
@@ -336,61 +811,125 @@ impl Pieces {
         Ok(())
     }
 
-    fn draw_move_hints(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
-        if let Some(selected) = self.selected {
-            cr.set_source_rgba(0.08, 0.47, 0.11, 0.5);
-
-            let radius = 0.12;
-            let corner = 1.8 * radius;
-
-            for square in state.move_targets(selected) {
-                if self.occupied().contains(square) {
-                    cr.move_to(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
-                    cr.rel_line_to(corner, 0.0);
-                    cr.rel_line_to(-corner, corner);
-                    cr.rel_line_to(0.0, -corner);
-                    cr.fill()?;
+    fn draw_selected_square(&self, cr: &Context, state: &BoardState, square: Square) -> Result<(), cairo::Error> {
+        match state.selection_style() {
+            SelectionStyle::Fill => {
+                cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+                cr.fill()
+            }
+            SelectionStyle::Outline => {
+                let stroke = 0.08;
+                cr.set_line_width(stroke);
+                cr.rectangle(file_to_float(square.file()) + 0.5 * stroke,
+                             7.0 - rank_to_float(square.rank()) + 0.5 * stroke,
+                             1.0 - stroke, 1.0 - stroke);
+                cr.stroke()
+            }
+        }
+    }
 
-                    cr.move_to(1.0 + file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
-                    cr.rel_line_to(0.0, corner);
-                    cr.rel_line_to(-corner, -corner);
-                    cr.rel_line_to(corner, 0.0);
-                    cr.fill()?;
+    fn draw_all_targets(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
+        if let Some(color) = state.all_targets() {
+            let origs = self.figurines.iter()
+                .filter(|f| !f.fading && f.piece.color == color)
+                .fold(Bitboard(0), |mut origs, f| {
+                    origs.add(f.square);
+                    origs
+                });
 
-                    cr.move_to(file_to_float(square.file()), 8.0 - rank_to_float(square.rank()));
-                    cr.rel_line_to(corner, 0.0);
-                    cr.rel_line_to(-corner, -corner);
-                    cr.rel_line_to(0.0, corner);
-                    cr.fill()?;
+            let (r, g, b, a) = state.theme().hint_quiet;
+            cr.set_source_rgba(r, g, b, a * 0.5);
 
-                    cr.move_to(1.0 + file_to_float(square.file()), 8.0 - rank_to_float(square.rank()));
-                    cr.rel_line_to(-corner, 0.0);
-                    cr.rel_line_to(corner, -corner);
-                    cr.rel_line_to(0.0, corner);
-                    cr.fill()?;
-                } else {
-                    cr.arc(0.5 + file_to_float(square.file()),
-                           7.5 - rank_to_float(square.rank()),
-                           radius, 0.0, 2.0 * PI);
-                    cr.fill()?;
-                }
+            for square in state.hint_targets_from(origs) {
+                cr.rectangle(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()), 1.0, 1.0);
+            }
+
+            cr.fill()?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_move_hints(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
+        let radius = 0.12;
+        let corner = 1.8 * radius;
+
+        for square in self.hint_squares(state) {
+            if self.occupied().contains(square) {
+                let (r, g, b, a) = state.theme().hint_capture;
+                cr.set_source_rgba(r, g, b, a);
+
+                cr.move_to(file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+                cr.rel_line_to(corner, 0.0);
+                cr.rel_line_to(-corner, corner);
+                cr.rel_line_to(0.0, -corner);
+                cr.fill()?;
+
+                cr.move_to(1.0 + file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+                cr.rel_line_to(0.0, corner);
+                cr.rel_line_to(-corner, -corner);
+                cr.rel_line_to(corner, 0.0);
+                cr.fill()?;
+
+                cr.move_to(file_to_float(square.file()), 8.0 - rank_to_float(square.rank()));
+                cr.rel_line_to(corner, 0.0);
+                cr.rel_line_to(-corner, -corner);
+                cr.rel_line_to(0.0, corner);
+                cr.fill()?;
+
+                cr.move_to(1.0 + file_to_float(square.file()), 8.0 - rank_to_float(square.rank()));
+                cr.rel_line_to(-corner, 0.0);
+                cr.rel_line_to(corner, -corner);
+                cr.rel_line_to(0.0, corner);
+                cr.fill()?;
+            } else {
+                let (r, g, b, a) = state.theme().hint_quiet;
+                cr.set_source_rgba(r, g, b, a);
+
+                cr.arc(0.5 + file_to_float(square.file()),
+                       7.5 - rank_to_float(square.rank()),
+                       radius, 0.0, 2.0 * PI);
+                cr.fill()?;
             }
         }
 
         Ok(())
     }
 
+    /// Squares with move hints for the selected piece.
+    fn hint_squares(&self, state: &BoardState) -> Bitboard {
+        match self.selected.square {
+            Some(_) if !state.show_move_hints() => Bitboard(0),
+            Some(selected) if self.selected.pondering => self.ponder_targets(selected),
+            Some(selected) => state.hint_targets(selected),
+            None => Bitboard(0),
+        }
+    }
+
     pub(crate) fn draw_drag(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
         match self.drag {
             Some(ref drag) if drag.threshold => {
-                cr.push_group();
+                cr.save()?;
                 cr.translate(drag.pos.0, drag.pos.1);
-                cr.rotate(state.orientation().fold_wb(0.0, PI));
+                state.upright(cr);
                 cr.translate(-0.5, -0.5);
-                cr.scale(state.piece_set().scale(), state.piece_set().scale());
-                state.piece_set().by_piece(&drag.piece).render_cairo(cr);
-                cr.pop_group_to_source()?;
-                cr.paint()?;
+                state.piece_set().render(cr, &drag.piece, state.piece_opacity())?;
+                cr.restore()?;
+
+                if state.drag_coordinate_tooltip() {
+                    if let Some(square) = pos_to_square(drag.pos) {
+                        // above the piece, as seen by the viewer
+                        let (x, y) = (drag.pos.0, drag.pos.1 + state.orientation().fold_wb(-0.65, 0.65));
+
+                        cr.set_source_rgba(0.0, 0.0, 0.0, 0.6);
+                        cr.rectangle(x - 0.2, y - 0.12, 0.4, 0.24);
+                        cr.fill()?;
+
+                        cr.set_source_rgb(1.0, 1.0, 1.0);
+                        cr.set_font_size(0.2);
+                        state.draw_text(cr, (x, y), &square.to_string())?;
+                    }
+                }
             }
             _ => {}
         }
@@ -399,6 +938,19 @@ impl Pieces {
     }
 }
 
+impl Selection {
+    fn set(&mut self, square: Option<Square>) {
+        if square != self.square {
+            if self.square.is_some() {
+                self.previous = self.square;
+            }
+            self.square = square;
+            self.since = SteadyTime::now();
+        }
+        self.pondering = false;
+    }
+}
+
 impl Figurine {
     pub fn piece(&self) -> &Piece {
         &self.piece
@@ -408,37 +960,208 @@ impl Figurine {
         self.start = pos;
         self.time = SteadyTime::now();
         self.elapsed = 0.0;
+        self.promoted = false;
+        self.appearing = false;
     }
 
-    fn pos(&self) -> (f64, f64) {
+    fn pos(&self, easing: Easing) -> (f64, f64) {
         if self.fading {
             self.start
         } else {
             let end = square_to_pos(self.square);
-            (ease(self.start.0, end.0, self.elapsed), ease(self.start.1, end.1, self.elapsed))
+            (easing.ease(self.start.0, end.0, self.elapsed), easing.ease(self.start.1, end.1, self.elapsed))
         }
     }
 
-    fn alpha(&self) -> f64 {
+    fn alpha(&self, easing: Easing) -> f64 {
         if self.replaced {
-            ease(0.5, 0.0, self.elapsed)
+            easing.ease(0.5, 0.0, self.elapsed)
         } else if self.fading {
-            ease(1.0, 0.0, self.elapsed)
+            easing.ease(1.0, 0.0, self.elapsed)
+        } else if self.appearing {
+            easing.ease(0.0, 1.0, self.elapsed)
         } else {
             1.0
         }
     }
 
-    fn queue_animation(&mut self, ctx: &WidgetContext) {
+    fn queue_animation(&mut self, ctx: &WidgetContext, easing: Easing, duration: f64) {
         if self.elapsed < 1.0 {
-            let pos = self.pos();
+            let pos = self.pos(easing);
             ctx.queue_draw_rect(pos.0 - 0.5, pos.1 - 0.5, 1.0, 1.0);
 
             let now = SteadyTime::now();
-            self.elapsed = ((now - self.time).num_milliseconds() as f64 / 300.0).min(1.0);
+            self.elapsed = if duration > 0.0 {
+                ((now - self.time).num_milliseconds() as f64 / (duration * 1000.0)).min(1.0)
+            } else {
+                1.0
+            };
 
-            let pos = self.pos();
+            let pos = self.pos(easing);
             ctx.queue_draw_rect(pos.0 - 0.5, pos.1 - 0.5, 1.0, 1.0);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(pieces: &Pieces) -> Vec<(Square, Piece, (f64, f64), f64, SteadyTime, bool)> {
+        pieces.figurines.iter()
+            .map(|f| (f.square, f.piece, f.start, f.elapsed, f.time, f.fading))
+            .collect()
+    }
+
+    #[test]
+    fn test_from_fen() {
+        let pieces = Pieces::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR").expect("valid board fen");
+        assert_eq!(pieces.board(), Board::default());
+        assert_eq!(pieces.occupied().count(), 32);
+
+        assert!(Pieces::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP").is_err());
+        assert!(Pieces::from_fen("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR").is_err());
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let from = Board::default();
+        let to: Board = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR".parse().expect("valid board fen");
+
+        let state = BoardState::new();
+        let mut pieces = Pieces::new_from_board(&from);
+        pieces.set_animation_duration(0.5);
+        pieces.set_slide_easing(Easing::InOutCubic);
+        assert!(pieces.select(&state, Square::G1));
+
+        pieces.interpolate(&from, &to, 0.25);
+        assert!((pieces.animation_duration() - 0.5).abs() < 1e-9);
+        assert_eq!(pieces.selected(), Some(Square::G1));
+
+        // linear from e2 at (4.5, 6.5) to e4 at (4.5, 4.5)
+        let figurine = pieces.figurine_at(Square::E4).expect("pawn on e4");
+        let (x, y) = figurine.pos(pieces.slide_easing());
+        assert!((x - 4.5).abs() < 1e-9);
+        assert!((y - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_set_board_twice() {
+        let mut pieces = Pieces::new_from_board(&Board::default());
+        let board: Board = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR".parse().expect("valid board fen");
+
+        let diff = pieces.set_board(&board);
+        assert!(diff.changed.contains(Square::E2));
+        assert!(diff.changed.contains(Square::E4));
+        let before = snapshot(&pieces);
+
+        let diff = pieces.set_board(&board);
+        assert!(diff.changed.is_empty());
+        assert!(diff.captured.is_empty());
+        assert_eq!(snapshot(&pieces), before);
+        assert_eq!(pieces.board(), board);
+    }
+
+    #[test]
+    fn test_two_click_move() {
+        let state = BoardState::new();
+        let mut pieces = Pieces::new_from_board(&Board::default());
+
+        assert!(matches!(pieces.click(&state, 1, Some(Square::E2)), Click::Selected));
+        assert_eq!(pieces.selected(), Some(Square::E2));
+
+        match pieces.click(&state, 1, Some(Square::E4)) {
+            Click::Move(orig, dest) => {
+                assert_eq!((orig, dest), (Square::E2, Square::E4));
+                assert!(matches!(pieces.move_msg(&state, orig, dest),
+                                 GroundMsg::UserMove(Square::E2, Square::E4, None)));
+            }
+            _ => panic!("expected a move"),
+        }
+        assert_eq!(pieces.selected(), None);
+    }
+
+    #[test]
+    fn test_click_empty_square() {
+        let state = BoardState::new();
+        let mut pieces = Pieces::new_from_board(&Board::default());
+
+        assert!(matches!(pieces.click(&state, 1, Some(Square::E4)), Click::Selected));
+        assert_eq!(pieces.selected(), None);
+    }
+
+    #[test]
+    fn test_hints_for_selected_piece() {
+        let mut state = BoardState::new();
+        let mut pieces = Pieces::new_from_board(&Board::default());
+        assert!(pieces.hint_squares(&state).is_empty());
+
+        assert!(pieces.select(&state, Square::E2));
+        let targets: Bitboard = [Square::E3, Square::E4].iter().cloned().collect();
+        assert_eq!(pieces.hint_squares(&state), targets);
+
+        assert!(pieces.select(&state, Square::G1));
+        let targets: Bitboard = [Square::F3, Square::H3].iter().cloned().collect();
+        assert_eq!(pieces.hint_squares(&state), targets);
+
+        state.set_show_move_hints(false);
+        assert!(pieces.hint_squares(&state).is_empty());
+
+        state.set_show_move_hints(true);
+        pieces.clear_selection();
+        assert!(pieces.hint_squares(&state).is_empty());
+    }
+
+    /// Press `button` on e2 with g1 selected, like the widget does.
+    fn press(state: &BoardState, button: u32) -> (Pieces, Click) {
+        let mut pieces = Pieces::new_from_board(&Board::default());
+        assert!(pieces.select(state, Square::G1));
+
+        let click = pieces.click(state, button, Some(Square::E2));
+        if !matches!(click, Click::Move(..)) {
+            pieces.start_drag(state, button, Some(Square::E2), (4.5, 6.5));
+        }
+        (pieces, click)
+    }
+
+    #[test]
+    fn test_left_button() {
+        let (pieces, click) = press(&BoardState::new(), 1);
+        assert!(matches!(click, Click::Selected));
+        assert_eq!(pieces.selected(), Some(Square::E2));
+        assert_eq!(pieces.dragging(), Some((Square::E2, (4.5, 6.5))));
+    }
+
+    #[test]
+    fn test_middle_button() {
+        let mut state = BoardState::new();
+        let (pieces, click) = press(&state, 2);
+        assert!(matches!(click, Click::Selected));
+        assert_eq!(pieces.selected(), None);
+        assert!(pieces.dragging().is_none());
+
+        state.set_middle_click_deselects(false);
+        let (pieces, click) = press(&state, 2);
+        assert!(matches!(click, Click::Ignored));
+        assert_eq!(pieces.selected(), Some(Square::G1));
+        assert!(pieces.dragging().is_none());
+    }
+
+    #[test]
+    fn test_right_button() {
+        let (pieces, click) = press(&BoardState::new(), 3);
+        assert!(matches!(click, Click::Selected));
+        assert_eq!(pieces.selected(), None);
+        assert!(pieces.dragging().is_none());
+    }
+
+    #[test]
+    fn test_other_buttons() {
+        for button in 4..10 {
+            let (pieces, click) = press(&BoardState::new(), button);
+            assert!(matches!(click, Click::Ignored));
+            assert_eq!(pieces.selected(), Some(Square::G1));
+            assert!(pieces.dragging().is_none());
+        }
+    }
+}
@@ -14,35 +14,116 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use rsvg::Handle;
+use std::cell::RefCell;
+use std::cmp::max;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use cairo::{Context, Format, ImageSurface, SurfaceType};
+use rsvg::{Handle, HandleExt};
 
 use shakmaty::{Color, Role, Piece};
 
+#[derive(Clone)]
+struct PieceImage {
+    handle: Handle,
+    /// Scale that fits the image into a unit square.
+    scale: f64,
+}
+
+impl PieceImage {
+    fn merida(handle: Handle) -> Option<PieceImage> {
+        Some(PieceImage { handle, scale: MERIDA_SCALE })
+    }
+}
+
+#[derive(Clone)]
 struct PieceSetSide {
-    pawn: Handle,
-    knight: Handle,
-    bishop: Handle,
-    rook: Handle,
-    queen: Handle,
-    king: Handle,
+    pawn: Option<PieceImage>,
+    knight: Option<PieceImage>,
+    bishop: Option<PieceImage>,
+    rook: Option<PieceImage>,
+    queen: Option<PieceImage>,
+    king: Option<PieceImage>,
 }
 
 impl PieceSetSide {
-    fn by_role(&self, role: Role) -> &Handle {
+    fn by_role(&self, role: Role) -> Option<&PieceImage> {
         match role {
-            Role::Pawn => &self.pawn,
-            Role::Knight => &self.knight,
-            Role::Bishop => &self.bishop,
-            Role::Rook => &self.rook,
-            Role::Queen => &self.queen,
-            Role::King => &self.king,
+            Role::Pawn => self.pawn.as_ref(),
+            Role::Knight => self.knight.as_ref(),
+            Role::Bishop => self.bishop.as_ref(),
+            Role::Rook => self.rook.as_ref(),
+            Role::Queen => self.queen.as_ref(),
+            Role::King => self.king.as_ref(),
         }
     }
 }
 
+/// A set of piece images.
+#[derive(Clone)]
 pub struct PieceSet {
     black: PieceSetSide,
     white: PieceSetSide,
+    fallback: Option<Box<PieceSet>>,
+    fallbacks: Vec<Piece>,
+    cache: RefCell<PieceCache>,
+}
+
+/// Number of sizes to keep rasterized pieces for. Older sizes are evicted,
+/// e.g. while the widget is resized.
+const CACHED_SIZES: usize = 4;
+
+/// Rasterized pieces, keyed by piece and size in device pixels.
+#[derive(Clone, Default)]
+struct PieceCache {
+    surfaces: HashMap<(Piece, u32), ImageSurface>,
+    /// Cached sizes, most recently used last.
+    sizes: Vec<u32>,
+}
+
+impl PieceCache {
+    fn get(&mut self, piece: Piece, size: u32) -> Option<ImageSurface> {
+        self.touch(size);
+        self.surfaces.get(&(piece, size)).cloned()
+    }
+
+    fn insert(&mut self, piece: Piece, size: u32, surface: ImageSurface) {
+        self.touch(size);
+
+        while self.sizes.len() > CACHED_SIZES {
+            let evicted = self.sizes.remove(0);
+            self.surfaces.retain(|&(_, s), _| s != evicted);
+        }
+
+        self.surfaces.insert((piece, size), surface);
+    }
+
+    /// Mark `size` as the most recently used.
+    fn touch(&mut self, size: u32) {
+        self.sizes.retain(|&s| s != size);
+        self.sizes.push(size);
+    }
+}
+
+/// Scale of the bundled merida pieces.
+const MERIDA_SCALE: f64 = 1.0 / 177.0;
+
+/// Scale that fits an SVG into a unit square, based on its size.
+fn fit_scale(handle: &Handle) -> Option<f64> {
+    let dimensions = handle.dimensions();
+    let size = max(dimensions.width, dimensions.height);
+    if size > 0 { Some(1.0 / f64::from(size)) } else { None }
+}
+
+impl fmt::Debug for PieceSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PieceSet").field("fallbacks", &self.fallbacks).finish()
+    }
 }
 
 impl PieceSet {
@@ -50,34 +131,218 @@ impl PieceSet {
         color.fold_wb(&self.white, &self.black)
     }
 
+    fn image(&self, piece: &Piece) -> &PieceImage {
+        match self.by_color(piece.color).by_role(piece.role) {
+            Some(image) => image,
+            None => self.fallback.as_ref().expect("fallback for incomplete piece set").image(piece),
+        }
+    }
+
     pub fn by_piece(&self, piece: &Piece) -> &Handle {
-        self.by_color(piece.color).by_role(piece.role)
+        &self.image(piece).handle
     }
 
+    /// Pieces that were missing from a custom set and are rendered using
+    /// the built-in merida set instead.
+    pub fn fallbacks(&self) -> &[Piece] {
+        &self.fallbacks
+    }
+
+    /// Scale that fits the white pawn into a unit square. Images of a
+    /// custom set may have different sizes, see `scale_of()`.
     pub fn scale(&self) -> f64 {
-        1.0 / 177.0
+        self.scale_of(&Color::White.pawn())
+    }
+
+    /// Scale that fits the image of `piece` into a unit square.
+    pub fn scale_of(&self, piece: &Piece) -> f64 {
+        self.image(piece).scale
+    }
+
+    /// Draw `piece` into the unit square at the origin with the given
+    /// opacity. On raster targets the image is rasterized once for each
+    /// size in device pixels and reused, so that animations do not render
+    /// the SVG on every frame.
+    pub fn render(&self, cr: &Context, piece: &Piece, alpha: f64) -> Result<(), cairo::Error> {
+        let (dx, dy) = cr.user_to_device_distance(1.0, 0.0)?;
+        let size = dx.hypot(dy).ceil();
+
+        // keep exported vector graphics free of bitmaps
+        let vector = matches!(cr.target().type_(), SurfaceType::Svg | SurfaceType::Pdf | SurfaceType::Ps);
+
+        cr.save()?;
+
+        if vector || size < 1.0 {
+            cr.push_group();
+            let scale = self.scale_of(piece);
+            cr.scale(scale, scale);
+            self.by_piece(piece).render_cairo(cr);
+            cr.pop_group_to_source()?;
+        } else {
+            let surface = self.surface(piece, size as u32)?;
+            cr.rectangle(0.0, 0.0, 1.0, 1.0);
+            cr.clip();
+            cr.scale(1.0 / size, 1.0 / size);
+            cr.set_source_surface(&surface, 0.0, 0.0)?;
+        }
+
+        cr.paint_with_alpha(alpha)?;
+        cr.restore()
+    }
+
+    /// The image of `piece` rasterized to `size` by `size` pixels.
+    fn surface(&self, piece: &Piece, size: u32) -> Result<ImageSurface, cairo::Error> {
+        let mut cache = self.cache.borrow_mut();
+
+        if let Some(surface) = cache.get(*piece, size) {
+            return Ok(surface);
+        }
+
+        let surface = ImageSurface::create(Format::ARgb32, size as i32, size as i32)?;
+        {
+            let cr = Context::new(&surface)?;
+            let scale = f64::from(size) * self.scale_of(piece);
+            cr.scale(scale, scale);
+            self.by_piece(piece).render_cairo(&cr);
+        }
+
+        cache.insert(*piece, size, surface.clone());
+        Ok(surface)
     }
 }
 
 impl PieceSet {
     pub fn merida() -> PieceSet {
         PieceSet {
+            fallback: None,
+            fallbacks: Vec::new(),
+            cache: RefCell::new(PieceCache::default()),
             black: PieceSetSide {
-                pawn: Handle::from_data(include_bytes!("merida/bP.svg")).expect("merida/bP.svg"),
-                knight: Handle::from_data(include_bytes!("merida/bN.svg")).expect("merida/bN.svg"),
-                bishop: Handle::from_data(include_bytes!("merida/bB.svg")).expect("merida/bB.svg"),
-                rook: Handle::from_data(include_bytes!("merida/bR.svg")).expect("merida/bR.svg"),
-                queen: Handle::from_data(include_bytes!("merida/bQ.svg")).expect("merida/bQ.svg"),
-                king: Handle::from_data(include_bytes!("merida/bK.svg")).expect("merida/bK.svg"),
+                pawn: PieceImage::merida(Handle::from_data(include_bytes!("merida/bP.svg")).expect("merida/bP.svg")),
+                knight: PieceImage::merida(Handle::from_data(include_bytes!("merida/bN.svg")).expect("merida/bN.svg")),
+                bishop: PieceImage::merida(Handle::from_data(include_bytes!("merida/bB.svg")).expect("merida/bB.svg")),
+                rook: PieceImage::merida(Handle::from_data(include_bytes!("merida/bR.svg")).expect("merida/bR.svg")),
+                queen: PieceImage::merida(Handle::from_data(include_bytes!("merida/bQ.svg")).expect("merida/bQ.svg")),
+                king: PieceImage::merida(Handle::from_data(include_bytes!("merida/bK.svg")).expect("merida/bK.svg")),
             },
             white: PieceSetSide {
-                pawn: Handle::from_data(include_bytes!("merida/wP.svg")).expect("merida/wP.svg"),
-                knight: Handle::from_data(include_bytes!("merida/wN.svg")).expect("merida/wN.svg"),
-                bishop: Handle::from_data(include_bytes!("merida/wB.svg")).expect("merida/wB.svg"),
-                rook: Handle::from_data(include_bytes!("merida/wR.svg")).expect("merida/wR.svg"),
-                queen: Handle::from_data(include_bytes!("merida/wQ.svg")).expect("merida/wQ.svg"),
-                king: Handle::from_data(include_bytes!("merida/wK.svg")).expect("merida/wK.svg"),
+                pawn: PieceImage::merida(Handle::from_data(include_bytes!("merida/wP.svg")).expect("merida/wP.svg")),
+                knight: PieceImage::merida(Handle::from_data(include_bytes!("merida/wN.svg")).expect("merida/wN.svg")),
+                bishop: PieceImage::merida(Handle::from_data(include_bytes!("merida/wB.svg")).expect("merida/wB.svg")),
+                rook: PieceImage::merida(Handle::from_data(include_bytes!("merida/wR.svg")).expect("merida/wR.svg")),
+                queen: PieceImage::merida(Handle::from_data(include_bytes!("merida/wQ.svg")).expect("merida/wQ.svg")),
+                king: PieceImage::merida(Handle::from_data(include_bytes!("merida/wK.svg")).expect("merida/wK.svg")),
             },
         }
     }
 }
+
+impl PieceSet {
+    /// Load a custom piece set from a directory containing `wP.svg`,
+    /// `bN.svg`, etc. Each piece is scaled to fit a square based on the
+    /// size of its image.
+    ///
+    /// If `strict` is not set, missing files are substituted with the
+    /// corresponding merida piece and reported by
+    /// [`fallbacks()`](#method.fallbacks).
+    pub fn from_dir<P: AsRef<Path>>(dir: P, strict: bool) -> Result<PieceSet, PieceSetError> {
+        let dir = dir.as_ref();
+        let mut fallbacks = Vec::new();
+
+        let mut load = |piece: Piece| -> Result<Option<PieceImage>, PieceSetError> {
+            let path = dir.join(format!("{}{}.svg", piece.color.fold_wb('w', 'b'), piece.role.upper_char()));
+            match fs::read(&path) {
+                Ok(data) => {
+                    let handle = Handle::from_data(&data).map_err(|err| PieceSetError::Svg(path.clone(), err.to_string()))?;
+                    let scale = fit_scale(&handle).ok_or_else(|| PieceSetError::Svg(path, "empty image".to_owned()))?;
+                    Ok(Some(PieceImage { handle, scale }))
+                }
+                Err(ref err) if !strict && err.kind() == io::ErrorKind::NotFound => {
+                    fallbacks.push(piece);
+                    Ok(None)
+                }
+                Err(err) => Err(PieceSetError::Io(path, err)),
+            }
+        };
+
+        let black = PieceSetSide {
+            pawn: load(Role::Pawn.of(Color::Black))?,
+            knight: load(Role::Knight.of(Color::Black))?,
+            bishop: load(Role::Bishop.of(Color::Black))?,
+            rook: load(Role::Rook.of(Color::Black))?,
+            queen: load(Role::Queen.of(Color::Black))?,
+            king: load(Role::King.of(Color::Black))?,
+        };
+
+        let white = PieceSetSide {
+            pawn: load(Role::Pawn.of(Color::White))?,
+            knight: load(Role::Knight.of(Color::White))?,
+            bishop: load(Role::Bishop.of(Color::White))?,
+            rook: load(Role::Rook.of(Color::White))?,
+            queen: load(Role::Queen.of(Color::White))?,
+            king: load(Role::King.of(Color::White))?,
+        };
+
+        Ok(PieceSet {
+            black,
+            white,
+            fallback: if fallbacks.is_empty() { None } else { Some(Box::new(PieceSet::merida())) },
+            fallbacks,
+            cache: RefCell::new(PieceCache::default()),
+        })
+    }
+}
+
+/// Error when loading a custom piece set.
+#[derive(Debug)]
+pub enum PieceSetError {
+    /// A piece file could not be read.
+    Io(PathBuf, io::Error),
+    /// A piece file is not a valid SVG.
+    Svg(PathBuf, String),
+}
+
+impl fmt::Display for PieceSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PieceSetError::Io(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            PieceSetError::Svg(ref path, ref err) => write!(f, "{}: invalid svg: {}", path.display(), err),
+        }
+    }
+}
+
+impl Error for PieceSetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn assert_complete(piece_set: &PieceSet) {
+        for &color in &[Color::White, Color::Black] {
+            for &role in &[Role::Pawn, Role::Knight, Role::Bishop, Role::Rook, Role::Queen, Role::King] {
+                assert!(piece_set.scale_of(&role.of(color)) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_merida_complete() {
+        let merida = PieceSet::merida();
+        assert!(merida.fallbacks().is_empty());
+        assert_complete(&merida);
+    }
+
+    #[test]
+    fn test_from_dir_fallbacks() {
+        let dir = env::temp_dir().join("chessground-test-empty-pieceset");
+        fs::create_dir_all(&dir).expect("create temp dir");
+
+        let piece_set = PieceSet::from_dir(&dir, false).expect("fallback to merida");
+        assert_eq!(piece_set.fallbacks().len(), 12);
+        assert_complete(&piece_set);
+
+        assert!(PieceSet::from_dir(&dir, true).is_err());
+    }
+}
@@ -19,15 +19,18 @@ use std::f64::consts::PI;
 use time::SteadyTime;
 
 use gtk::prelude::*;
+use gdk::keys::Key;
+use gdk::keys::constants as key;
 use cairo::Context;
-use rsvg::HandleExt;
 
-use shakmaty::{Square, Rank, Color, Role, MoveList};
+use shakmaty::{Square, File, Rank, Color, Role, MoveList};
 
-use util::{ease, file_to_float, square_to_pos};
+use util::{ease, file_to_float, rank_to_float, square_to_pos};
 use pieces::Pieces;
-use boardstate::BoardState;
-use ground::{WidgetContext, EventContext, GroundMsg};
+use boardstate::{BoardState, PromotionLayout};
+use ground::{WidgetContext, EventContext, GroundMsg, Stream};
+
+const ROLES: [Role; 6] = [Role::Queen, Role::Rook, Role::Bishop, Role::Knight, Role::King, Role::Pawn];
 
 pub struct Promotable {
     promoting: Option<Promoting>,
@@ -85,6 +88,16 @@ impl Promotable {
         }
     }
 
+    /// The highlighted promotion choice, if a promotion is in progress.
+    pub fn candidate(&self, state: &BoardState) -> Option<Role> {
+        self.promoting.as_ref()
+            .and_then(|p| p.hover.as_ref().and_then(|h| p.role_at(h.square, state.promotion_layout())))
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.promoting.is_some()
+    }
+
     pub fn is_promoting(&self, orig: Square) -> bool {
         self.promoting.as_ref().map_or(false, |p| p.orig == orig)
     }
@@ -102,9 +115,12 @@ impl Promotable {
     pub(crate) fn mouse_move(&mut self, ctx: &EventContext) {
         if let Some(ref mut promoting) = self.promoting {
             let previous = promoting.hover.as_ref().map(|h| h.square);
-            let square = ctx.square().filter(|sq| sq.file() == promoting.dest.file());
+            let layout = ctx.board_state().promotion_layout();
+            let square = ctx.square().filter(|sq| promoting.role_at(*sq, layout).is_some());
 
             if square != previous {
+                // the destination shows a preview of the hovered choice
+                ctx.widget().queue_draw_square(promoting.dest);
                 if let Some(sq) = previous {
                     ctx.widget().queue_draw_square(sq);
                 }
@@ -122,38 +138,81 @@ impl Promotable {
     }
 
     pub(crate) fn mouse_down(&mut self, pieces: &mut Pieces, ctx: &EventContext) -> Inhibit {
-        if let Some(promoting) = self.promoting.take() {
-            ctx.widget().queue_draw();
-
-            if let Some(figurine) = pieces.figurine_at_mut(promoting.orig) {
-                // animate the figurine when cancelling
-                figurine.set_pos(square_to_pos(promoting.dest));
+        if let Some(promoting) = self.close(pieces, ctx.widget()) {
+            let role = ctx.square().and_then(|sq| promoting.role_at(sq, ctx.board_state().promotion_layout()));
+            if role.is_some() {
+                ctx.stream().emit(GroundMsg::UserMove(promoting.orig, promoting.dest, role));
+                return Inhibit(true);
             }
+        }
 
-            if let Some(square) = ctx.square() {
-                let side = promoting.orientation();
-                let base = i8::from(promoting.dest.rank());
-
-                if square.file() == promoting.dest.file() {
-                    let role = match i8::from(square.rank()) {
-                        r if r == base => Some(Role::Queen),
-                        r if r == base + side.fold_wb(-1, 1) => Some(Role::Rook),
-                        r if r == base + side.fold_wb(-2, 2) => Some(Role::Bishop),
-                        r if r == base + side.fold_wb(-3, 3) => Some(Role::Knight),
-                        r if r == base + side.fold_wb(-4, 4) => Some(Role::King),
-                        r if r == base + side.fold_wb(-5, 5) => Some(Role::Pawn),
-                        _ => None,
-                    };
+        Inhibit(false)
+    }
 
+    /// Move the highlighted choice with the arrow keys, confirm it with
+    /// enter or cancel with escape.
+    pub(crate) fn key_press(&mut self, pieces: &mut Pieces, state: &BoardState, stream: &Stream, ctx: &WidgetContext, keyval: &Key) -> Inhibit {
+        let (choices, current) = match self.promoting {
+            Some(ref promoting) => {
+                let choices = promoting.choices(state);
+                let current = promoting.hover.as_ref().and_then(|h| choices.iter().position(|&sq| sq == h.square));
+                (choices, current)
+            }
+            None => return Inhibit(false),
+        };
+
+        let next = match *keyval {
+            key::Down | key::Right => current.map_or(0, |i| i + 1),
+            key::Up | key::Left => current.map_or(0, |i| i + choices.len() - 1),
+            key::Return | key::KP_Enter => {
+                let role = self.candidate(state);
+                if let Some(promoting) = self.close(pieces, ctx) {
                     if role.is_some() {
-                        ctx.stream().emit(GroundMsg::UserMove(promoting.orig, promoting.dest, role));
-                        return Inhibit(true);
+                        stream.emit(GroundMsg::UserMove(promoting.orig, promoting.dest, role));
                     }
                 }
+                return Inhibit(true);
+            }
+            key::Escape => {
+                self.close(pieces, ctx);
+                return Inhibit(true);
+            }
+            _ => return Inhibit(false),
+        };
+
+        if let Some(ref mut promoting) = self.promoting {
+            if !choices.is_empty() {
+                let square = choices[next % choices.len()];
+                if let Some(hover) = promoting.hover.take() {
+                    ctx.queue_draw_square(hover.square);
+                }
+                ctx.queue_draw_square(square);
+                ctx.queue_draw_square(promoting.dest);
+
+                promoting.hover = Some(Hover {
+                    square,
+                    since: SteadyTime::now(),
+                    elapsed: 0.0,
+                });
             }
         }
 
-        Inhibit(false)
+        Inhibit(true)
+    }
+
+    /// Close the dialog, animating the pawn back from the promotion square.
+    fn close(&mut self, pieces: &mut Pieces, ctx: &WidgetContext) -> Option<Promoting> {
+        let promoting = self.promoting.take();
+
+        if let Some(ref promoting) = promoting {
+            ctx.queue_draw();
+
+            if let Some(figurine) = pieces.figurine_at_mut(promoting.orig) {
+                figurine.set_pos(square_to_pos(promoting.dest));
+            }
+        }
+
+        promoting
     }
 
     pub(crate) fn draw(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
@@ -170,25 +229,52 @@ impl Promoting {
         Color::from_white(self.dest.rank() > Rank::Fourth)
     }
 
+    /// The square of the `offset`-th promotion choice.
+    fn choice_square(&self, offset: usize, layout: PromotionLayout) -> Square {
+        let (column, row) = match layout {
+            PromotionLayout::Column => (0, offset as i8),
+            PromotionLayout::Grid => ((offset % 2) as i8, (offset / 2) as i8),
+        };
+
+        let file = i8::from(self.dest.file()) + if self.dest.file() < File::E { column } else { -column };
+        let rank = i8::from(self.dest.rank()) - self.orientation().fold_wb(row, -row);
+        Square::new((rank * 8 + file) as u32)
+    }
+
+    /// The squares of the legal promotion choices.
+    fn choices(&self, state: &BoardState) -> Vec<Square> {
+        ROLES.iter().enumerate()
+            .filter(|&(_, role)| state.legal_move(self.orig, self.dest, Some(*role)))
+            .map(|(offset, _)| self.choice_square(offset, state.promotion_layout()))
+            .collect()
+    }
+
+    /// The promotion choice shown on `square`, if any.
+    fn role_at(&self, square: Square, layout: PromotionLayout) -> Option<Role> {
+        ROLES.iter().enumerate()
+            .find(|&(offset, _)| self.choice_square(offset, layout) == square)
+            .map(|(_, role)| *role)
+    }
+
     fn draw(&self, cr: &Context, state: &BoardState) -> Result<(), cairo::Error> {
         // make the board darker
         cr.rectangle(0.0, 0.0, 8.0, 8.0);
         cr.set_source_rgba(0.0, 0.0, 0.0, 0.5);
         cr.fill()?;
 
-        for (offset, role) in [Role::Queen, Role::Rook, Role::Bishop, Role::Knight, Role::King, Role::Pawn].iter().enumerate() {
+        for (offset, role) in ROLES.iter().enumerate() {
             if !state.legal_move(self.orig, self.dest, Some(*role)) {
                 continue;
             }
 
-            let rank = i8::from(self.dest.rank()) - self.orientation().fold_wb(offset as i8, -(offset as i8));
-            let light = (i8::from(self.dest.file()) + rank) & 1 == 1;
+            let square = self.choice_square(offset, state.promotion_layout());
+            let (x, y) = (file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
 
             cr.save()?;
-            cr.rectangle(file_to_float(self.dest.file()), 7.0 - f64::from(rank), 1.0, 1.0);
+            cr.rectangle(x, y, 1.0, 1.0);
 
             // draw background
-            if light {
+            if square.is_light() {
                 cr.set_source_rgb(0.25, 0.25, 0.25);
             } else {
                 cr.set_source_rgb(0.18, 0.18, 0.18);
@@ -198,7 +284,7 @@ impl Promoting {
 
             // draw piece
             let radius = match self.hover {
-                Some(ref hover) if i8::from(hover.square.rank()) == rank => {
+                Some(ref hover) if hover.square == square => {
                     cr.set_source_rgb(ease(0.69, 1.0, hover.elapsed),
                                       ease(0.69, 0.65, hover.elapsed),
                                       ease(0.69, 0.0, hover.elapsed));
@@ -211,15 +297,46 @@ impl Promoting {
                 },
             };
 
-            cr.arc(0.5 + file_to_float(self.dest.file()), 7.5 - f64::from(rank), radius, 0.0, 2.0 * PI);
+            cr.arc(x + 0.5, y + 0.5, radius, 0.0, 2.0 * PI);
             cr.fill()?;
 
-            cr.translate(0.5 + file_to_float(self.dest.file()), 7.5 - f64::from(rank));
+            cr.translate(x + 0.5, y + 0.5);
             cr.scale(2f64.sqrt() * radius, 2f64.sqrt() * radius);
-            cr.rotate(state.orientation().fold_wb(0.0, PI));
+            state.upright(cr);
+            cr.translate(-0.5, -0.5);
+            state.piece_set().render(cr, &role.of(self.color), 1.0)?;
+
+            cr.restore()?;
+
+            if square == self.dest {
+                self.draw_preview(cr, state, x, y)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Preview the hovered choice in a corner of the destination square,
+    /// unless the choice on the destination itself is hovered.
+    fn draw_preview(&self, cr: &Context, state: &BoardState, x: f64, y: f64) -> Result<(), cairo::Error> {
+        let role = match self.hover {
+            Some(ref hover) if hover.square != self.dest => self.role_at(hover.square, state.promotion_layout()),
+            _ => None,
+        };
+
+        if let Some(role) = role {
+            cr.save()?;
+            cr.translate(x + 0.5, y + 0.5);
+            state.upright(cr);
+            cr.translate(0.28, 0.28);
+
+            cr.arc(0.0, 0.0, 0.2, 0.0, 2.0 * PI);
+            cr.set_source_rgba(0.1, 0.1, 0.1, 0.9);
+            cr.fill()?;
+
+            cr.scale(0.35, 0.35);
             cr.translate(-0.5, -0.5);
-            cr.scale(state.piece_set().scale(), state.piece_set().scale());
-            state.piece_set().by_piece(&role.of(self.color)).render_cairo(cr);
+            state.piece_set().render(cr, &role.of(self.color), 1.0)?;
 
             cr.restore()?;
         }
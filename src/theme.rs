@@ -0,0 +1,145 @@
+// This file is part of the chessground library.
+// Copyright (C) 2017 Niklas Fiekas <niklas.fiekas@backscattering.de>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+/// Board colors.
+///
+/// Colors are RGB or RGBA tuples with components from 0.0 to 1.0.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct BoardTheme {
+    /// Light squares.
+    pub light: (f64, f64, f64),
+    /// Dark squares.
+    pub dark: (f64, f64, f64),
+    /// Selected square and hovered move target.
+    pub selected: (f64, f64, f64, f64),
+    /// Legal move hints on empty squares.
+    pub hint_quiet: (f64, f64, f64, f64),
+    /// Legal move hints on occupied squares.
+    pub hint_capture: (f64, f64, f64, f64),
+    /// Last move squares.
+    pub last_move: (f64, f64, f64, f64),
+    /// Origin square of a premove.
+    pub premove_orig: (f64, f64, f64, f64),
+    /// Destination square of a premove.
+    pub premove_dest: (f64, f64, f64, f64),
+    /// Center of the glow around a king in check.
+    pub check_inner: (f64, f64, f64),
+    /// Edge of the glow around a king in check.
+    pub check_outer: (f64, f64, f64),
+    /// Radius of the glow around a king in check, in squares.
+    pub check_glow_radius: f64,
+    /// Border around the board.
+    pub border: (f64, f64, f64),
+    /// Coordinates on the border.
+    pub coordinates: (f64, f64, f64),
+}
+
+impl BoardTheme {
+    /// Blue squares, like the default lichess board.
+    pub fn blue() -> BoardTheme {
+        BoardTheme::default()
+    }
+
+    /// Brown squares, like the classic lichess board.
+    pub fn brown() -> BoardTheme {
+        BoardTheme {
+            light: (0.94, 0.85, 0.71),
+            dark: (0.71, 0.53, 0.39),
+            border: (0.30, 0.20, 0.13),
+            coordinates: (0.94, 0.85, 0.71),
+            ..BoardTheme::default()
+        }
+    }
+
+    /// High contrast black and white squares with strong highlights.
+    pub fn high_contrast() -> BoardTheme {
+        BoardTheme {
+            light: (1.0, 1.0, 1.0),
+            dark: (0.35, 0.35, 0.35),
+            selected: (0.0, 0.45, 0.70, 0.7),
+            hint_quiet: (0.0, 0.45, 0.70, 0.8),
+            hint_capture: (0.0, 0.45, 0.70, 0.8),
+            last_move: (0.94, 0.89, 0.26, 0.6),
+            premove_orig: (0.0, 0.0, 0.0, 0.5),
+            premove_dest: (0.0, 0.0, 0.0, 0.7),
+            check_inner: (1.0, 0.0, 1.0),
+            check_outer: (0.6, 0.0, 0.6),
+            check_glow_radius: 0.5f64.hypot(0.5),
+            border: (0.2, 0.2, 0.5),
+            coordinates: (0.8, 0.8, 0.8),
+        }
+    }
+
+    /// Colors that can be told apart with deuteranopia (green weakness),
+    /// avoiding red and green highlights.
+    pub fn deuteranopia() -> BoardTheme {
+        BoardTheme {
+            light: (0.87, 0.89, 0.90),
+            dark: (0.55, 0.64, 0.68),
+            selected: (0.0, 0.45, 0.70, 0.5),
+            hint_quiet: (0.0, 0.45, 0.70, 0.6),
+            hint_capture: (0.0, 0.45, 0.70, 0.6),
+            last_move: (0.90, 0.60, 0.0, 0.45),
+            premove_orig: (0.34, 0.71, 0.91, 0.4),
+            premove_dest: (0.34, 0.71, 0.91, 0.6),
+            check_inner: (0.80, 0.47, 0.65),
+            check_outer: (0.50, 0.20, 0.40),
+            check_glow_radius: 0.5f64.hypot(0.5),
+            border: (0.2, 0.2, 0.5),
+            coordinates: (0.8, 0.8, 0.8),
+        }
+    }
+
+    /// Colors that can be told apart with protanopia (red weakness),
+    /// using a bright glow instead of red, which appears dark.
+    pub fn protanopia() -> BoardTheme {
+        BoardTheme {
+            light: (0.87, 0.89, 0.90),
+            dark: (0.55, 0.64, 0.68),
+            selected: (0.0, 0.45, 0.70, 0.5),
+            hint_quiet: (0.0, 0.45, 0.70, 0.6),
+            hint_capture: (0.0, 0.45, 0.70, 0.6),
+            last_move: (0.34, 0.71, 0.91, 0.45),
+            premove_orig: (0.80, 0.47, 0.65, 0.4),
+            premove_dest: (0.80, 0.47, 0.65, 0.6),
+            check_inner: (0.94, 0.89, 0.26),
+            check_outer: (0.90, 0.60, 0.0),
+            check_glow_radius: 0.5f64.hypot(0.5),
+            border: (0.2, 0.2, 0.5),
+            coordinates: (0.8, 0.8, 0.8),
+        }
+    }
+}
+
+impl Default for BoardTheme {
+    fn default() -> BoardTheme {
+        BoardTheme {
+            light: (0.87, 0.89, 0.90),
+            dark: (0.55, 0.64, 0.68),
+            selected: (0.08, 0.47, 0.11, 0.5),
+            hint_quiet: (0.08, 0.47, 0.11, 0.5),
+            hint_capture: (0.08, 0.47, 0.11, 0.5),
+            last_move: (0.61, 0.78, 0.0, 0.41),
+            premove_orig: (0.08, 0.31, 0.51, 0.3),
+            premove_dest: (0.08, 0.31, 0.51, 0.5),
+            check_inner: (1.0, 0.0, 0.0),
+            check_outer: (0.66, 0.0, 0.0),
+            check_glow_radius: 0.5f64.hypot(0.5),
+            border: (0.2, 0.2, 0.5),
+            coordinates: (0.8, 0.8, 0.8),
+        }
+    }
+}
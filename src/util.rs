@@ -14,7 +14,17 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
-use shakmaty::{Square, File, Rank};
+use std::cmp::{min, max};
+use std::f64::consts::PI;
+
+use cairo::Matrix;
+
+use shakmaty::{Square, File, Rank, Role, Color, Bitboard, Board, Move, Position, MoveList};
+use shakmaty::attacks;
+use shakmaty::san::San;
+use shakmaty::uci::Uci;
+
+use boardstate::{BoardState, EVAL_BAR_LEFT};
 
 pub fn ease(start: f64, end: f64, t: f64) -> f64 {
     // ease in out cubic from https://gist.github.com/gre/1650294
@@ -47,3 +57,468 @@ pub fn rank_to_float(rank: Rank) -> f64 {
 pub fn file_to_float(file: File) -> f64 {
     f64::from(i8::from(file))
 }
+
+/// SAN of the legal moves, without check and checkmate suffixes.
+pub fn sans<P: Position>(pos: &P, legals: &MoveList) -> Vec<San> {
+    legals.iter().map(|m| San::from_move(pos, m)).collect()
+}
+
+/// Transform board coordinates to widget coordinates, for a board centered
+/// in the given rectangle. The board is kept at least 9 pixels large, so
+/// that the matrix stays invertible even before the widget has been
+/// allocated.
+pub fn compute_matrix(board_state: &BoardState, x: i32, y: i32, width: i32, height: i32) -> Matrix {
+    let (width, height) = (max(width, 0), max(height, 0));
+    let extent = board_extent(board_state);
+
+    // reserve room for the eval bar next to the border
+    let (content_width, shift) = content_extent(board_state);
+    let board_width = (f64::from(width) * extent / content_width).floor() as i32;
+
+    let square_size = match board_state.fixed_square_px() {
+        Some(px) => f64::from(max(px, 1)),
+        None => {
+            let size = min(board_width, height);
+            let size = max(board_state.max_size().map_or(size, |max_size| min(size, max_size)), 9);
+
+            // fit the corners of a tilted board into the allocation
+            let tilt = board_state.tilt().to_radians();
+            f64::from(size) / extent / (tilt.cos().abs() + tilt.sin().abs())
+        }
+    };
+
+    let mut matrix = board_matrix(board_state,
+                                  f64::from(x) + f64::from(width) / 2.0,
+                                  f64::from(y) + f64::from(height) / 2.0,
+                                  square_size);
+    matrix.translate(shift, 0.0);
+    matrix
+}
+
+/// Size of the board including the border, in squares.
+pub fn board_extent(board_state: &BoardState) -> f64 {
+    if board_state.has_border() { 9.0 } else { 8.0 }
+}
+
+/// Size of the widget for a board with a fixed square size, including the
+/// border and the eval bar, or `None` if the board scales with the widget.
+pub fn fixed_size(board_state: &BoardState) -> Option<(i32, i32)> {
+    board_state.fixed_square_px().map(|px| {
+        let px = f64::from(max(px, 1));
+        let (width, _) = content_extent(board_state);
+        ((px * width).ceil() as i32, (px * board_extent(board_state)).ceil() as i32)
+    })
+}
+
+/// Width of the board, its border and the eval bar, in squares, and how
+/// far the board is shifted to center all of them.
+pub fn content_extent(board_state: &BoardState) -> (f64, f64) {
+    let extent = board_extent(board_state);
+    if board_state.show_eval_bar() {
+        let margin = (extent - 8.0) / 2.0;
+        let (left, right) = (EVAL_BAR_LEFT.min(-margin), 8.0 + margin);
+        (right - left, 4.0 - (left + right) / 2.0)
+    } else {
+        (extent, 0.0)
+    }
+}
+
+/// Transform board coordinates, with the center of the board at
+/// (`center_x`, `center_y`) and `square_size` units per square.
+pub fn board_matrix(board_state: &BoardState, center_x: f64, center_y: f64, square_size: f64) -> Matrix {
+    let mut matrix = Matrix::identity();
+    matrix.translate(center_x, center_y);
+    matrix.scale(square_size, square_size);
+    matrix.rotate(board_state.orientation().fold_wb(0.0, PI) + board_state.tilt().to_radians());
+    if board_state.mirror_files() {
+        matrix.scale(-1.0, 1.0);
+    }
+    matrix.translate(-4.0, -4.0);
+    matrix
+}
+
+/// Area of the square in board coordinates, including the coordinates in
+/// the margin next to edge squares.
+pub fn square_area(square: Square) -> (f64, f64, f64, f64) {
+    let (mut x, mut y) = (file_to_float(square.file()), 7.0 - rank_to_float(square.rank()));
+    let (mut width, mut height) = (1.0, 1.0);
+
+    if square.file() == File::A {
+        x -= 0.5;
+        width += 0.5;
+    } else if square.file() == File::H {
+        width += 0.5;
+    }
+    if square.rank() == Rank::Eighth {
+        y -= 0.5;
+        height += 0.5;
+    } else if square.rank() == Rank::First {
+        height += 0.5;
+    }
+
+    (x, y, width, height)
+}
+
+/// Rectangle in widget coordinates that needs to be redrawn for a
+/// rectangle in board coordinates.
+pub fn damage_rect(matrix: &Matrix, x: f64, y: f64, width: f64, height: f64) -> (i32, i32, i32, i32) {
+    // round to square grid
+    let (x1, y1) = (x.floor(), y.floor());
+    let (x2, y2) = ((x + width).ceil(), (y + height).ceil());
+
+    // transform all corners to widget coordinates, since the board
+    // may be tilted
+    let corners = [
+        matrix.transform_point(x1, y1),
+        matrix.transform_point(x2, y1),
+        matrix.transform_point(x1, y2),
+        matrix.transform_point(x2, y2),
+    ];
+
+    let xmin = corners.iter().map(|c| c.0.floor() as i32).min().unwrap_or(0);
+    let ymin = corners.iter().map(|c| c.1.floor() as i32).min().unwrap_or(0);
+    let xmax = corners.iter().map(|c| c.0.ceil() as i32).max().unwrap_or(0);
+    let ymax = corners.iter().map(|c| c.1.ceil() as i32).max().unwrap_or(0);
+
+    (xmin, ymin, xmax - xmin, ymax - ymin)
+}
+
+/// Convert a move in UCI notation to a move on the board. Castling may be
+/// given as the king moving two squares or onto its own rook.
+///
+/// Returns `None` unless the move is pseudo-legal for a piece of `turn`
+/// (any side if `None`), e.g. for promotions that do not reach the back
+/// rank. Whether the move leaves the king in check is not checked.
+pub fn uci_to_move(board: &Board, turn: Option<Color>, uci: &Uci) -> Option<Move> {
+    match *uci {
+        Uci::Normal { from, to, promotion } => {
+            let piece = board.piece_at(from)?;
+            if let Some(turn) = turn {
+                if turn != piece.color {
+                    return None;
+                }
+            }
+
+            if piece.role == Role::King && promotion.is_none() {
+                let rook = if board.piece_at(to) == Some(piece.color.rook()) {
+                    Some(to)
+                } else if from.rank() == to.rank() && from.distance(to) == 2 {
+                    let file = if to > from { File::H } else { File::A };
+                    Some(Square::from_coords(file, from.rank()))
+                } else {
+                    None
+                };
+
+                if let Some(rook) = rook {
+                    return if from.rank() == piece.color.fold_wb(Rank::First, Rank::Eighth) &&
+                              board.piece_at(rook) == Some(piece.color.rook()) {
+                        Some(Move::Castle { king: from, rook })
+                    } else {
+                        None
+                    };
+                }
+            }
+
+            if board.by_color(piece.color).contains(to) {
+                return None;
+            }
+
+            if piece.role == Role::Pawn {
+                // promote exactly when reaching the back rank
+                let back_rank = piece.color.fold_wb(Rank::Eighth, Rank::First);
+                if (to.rank() == back_rank) != promotion.is_some() || promotion == Some(Role::Pawn) {
+                    return None;
+                }
+
+                let forward = piece.color.fold_wb(1, -1);
+                let distance = i8::from(to.rank()) - i8::from(from.rank());
+
+                if from.file() != to.file() {
+                    if distance != forward || from.distance(to) != 1 {
+                        return None;
+                    }
+                    if board.piece_at(to).is_none() {
+                        // en passant from the fifth rank
+                        return if from.rank() == piece.color.fold_wb(Rank::Fifth, Rank::Fourth) {
+                            Some(Move::EnPassant { from, to })
+                        } else {
+                            None
+                        };
+                    }
+                } else {
+                    let start_rank = piece.color.fold_wb(Rank::Second, Rank::Seventh);
+                    let between = Square::from_coords(from.file(), piece.color.fold_wb(Rank::Third, Rank::Sixth));
+                    let pushes = distance == forward ||
+                        (distance == 2 * forward && from.rank() == start_rank && board.piece_at(between).is_none());
+                    if !pushes || board.piece_at(to).is_some() {
+                        return None;
+                    }
+                }
+            } else if promotion.is_some() || !attacks::attacks(from, piece, board.occupied()).contains(to) {
+                return None;
+            }
+
+            Some(Move::Normal {
+                role: piece.role,
+                from,
+                capture: board.role_at(to),
+                to,
+                promotion,
+            })
+        }
+        Uci::Put { role, to } => {
+            if board.occupied().contains(to) {
+                None
+            } else {
+                Some(Move::Put { role, to })
+            }
+        }
+        Uci::Null => None,
+    }
+}
+
+/// Squares the piece on `square` could move to on a later turn, ignoring
+/// all other pieces, e.g. to check if a premove makes sense.
+pub fn premove_targets(board: &Board, square: Square) -> Bitboard {
+    let piece = match board.piece_at(square) {
+        Some(piece) => piece,
+        None => return Bitboard(0),
+    };
+
+    let mut targets = attacks::attacks(square, piece, Bitboard(0));
+
+    match piece.role {
+        Role::Pawn => {
+            let forward = piece.color.fold_wb(1, -1);
+            let steps = if square.rank() == piece.color.fold_wb(Rank::Second, Rank::Seventh) { 2 } else { 1 };
+            for step in 1..=steps {
+                let rank = i8::from(square.rank()) + forward * step;
+                if (0..8).contains(&rank) {
+                    targets.add(Square::from_coords(square.file(), Rank::new(rank as u32)));
+                }
+            }
+        }
+        Role::King if square.rank() == piece.color.fold_wb(Rank::First, Rank::Eighth) => {
+            // castling, with the king moving two squares or onto a rook
+            targets.add(Square::from_coords(File::C, square.rank()));
+            targets.add(Square::from_coords(File::G, square.rank()));
+            for rook in board.by_piece(piece.color.rook()) {
+                if rook.rank() == square.rank() {
+                    targets.add(rook);
+                }
+            }
+        }
+        _ => (),
+    }
+
+    targets
+}
+
+/// Move the pieces on the board, without checking legality. Pieces dropped
+/// with `Move::Put` have the color `turn`.
+pub fn play_on_board(board: &mut Board, m: &Move, turn: Color) {
+    match *m {
+        Move::Normal { from, to, promotion, .. } => {
+            if let Some(piece) = board.remove_piece_at(from) {
+                board.set_piece_at(to, promotion.map_or(piece, |role| role.of(piece.color)));
+            }
+        }
+        Move::EnPassant { from, to } => {
+            if let Some(piece) = board.remove_piece_at(from) {
+                board.set_piece_at(to, piece);
+            }
+            board.remove_piece_at(Square::from_coords(to.file(), from.rank()));
+        }
+        Move::Castle { king, rook } => {
+            let (king_file, rook_file) = if rook.file() > king.file() { (File::G, File::F) } else { (File::C, File::D) };
+            let king_piece = board.remove_piece_at(king);
+            let rook_piece = board.remove_piece_at(rook);
+            if let Some(piece) = king_piece {
+                board.set_piece_at(Square::from_coords(king_file, king.rank()), piece);
+            }
+            if let Some(piece) = rook_piece {
+                board.set_piece_at(Square::from_coords(rook_file, rook.rank()), piece);
+            }
+        }
+        Move::Put { role, to } => {
+            board.set_piece_at(to, role.of(turn));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cairo::{Context, Format, ImageSurface};
+
+    use boardstate::CoordinateMode;
+
+    fn is_finite(matrix: &Matrix) -> bool {
+        [matrix.xx, matrix.yx, matrix.xy, matrix.yy, matrix.x0, matrix.y0].iter().all(|v| v.is_finite())
+    }
+
+    #[test]
+    fn test_compute_matrix_unallocated() {
+        let mut board_state = BoardState::new();
+        for &(width, height) in &[(0, 0), (0, 500), (500, 0), (-1, -1)] {
+            let matrix = compute_matrix(&board_state, 0, 0, width, height);
+            assert!(is_finite(&matrix));
+            assert!(matrix.try_invert().is_ok());
+        }
+
+        board_state.set_fixed_square_px(Some(0));
+        let matrix = compute_matrix(&board_state, 0, 0, 0, 0);
+        assert!(is_finite(&matrix));
+        assert!(matrix.try_invert().is_ok());
+    }
+
+    fn a1_center(board_state: &BoardState) -> (f64, f64) {
+        compute_matrix(board_state, 0, 0, 900, 900).transform_point(0.5, 7.5)
+    }
+
+    #[test]
+    fn test_compute_matrix_orientation() {
+        let mut board_state = BoardState::new();
+        board_state.set_orientation(Color::White);
+        let (x, y) = a1_center(&board_state);
+        assert!(x < 450.0 && y > 450.0);
+
+        // a1 is in the top right corner when viewed from black
+        board_state.set_orientation(Color::Black);
+        let (bx, by) = a1_center(&board_state);
+        assert!((bx - (900.0 - x)).abs() < 1e-6);
+        assert!((by - (900.0 - y)).abs() < 1e-6);
+
+        // mirroring the files swaps left and right only
+        board_state.set_orientation(Color::White);
+        board_state.set_mirror_files(true);
+        let (mx, my) = a1_center(&board_state);
+        assert!((mx - (900.0 - x)).abs() < 1e-6);
+        assert!((my - y).abs() < 1e-6);
+    }
+
+    fn covers(rect: (i32, i32, i32, i32), point: (f64, f64)) -> bool {
+        let (x, y, width, height) = rect;
+        (f64::from(x)..=f64::from(x + width)).contains(&point.0) &&
+        (f64::from(y)..=f64::from(y + height)).contains(&point.1)
+    }
+
+    #[test]
+    fn test_damage_rect_covers_coordinates() {
+        for &orientation in &[Color::White, Color::Black] {
+            let mut board_state = BoardState::new();
+            board_state.set_orientation(orientation);
+            let matrix = compute_matrix(&board_state, 0, 0, 900, 900);
+
+            // rank label left of a1 and file label below it
+            let (x, y, width, height) = square_area(Square::A1);
+            let rect = damage_rect(&matrix, x, y, width, height);
+            assert!(covers(rect, matrix.transform_point(-0.25, 7.5)));
+            assert!(covers(rect, matrix.transform_point(0.5, 8.25)));
+            assert!(covers(rect, matrix.transform_point(0.5, 7.5)));
+
+            // labels on the other side of h8
+            let (x, y, width, height) = square_area(Square::H8);
+            let rect = damage_rect(&matrix, x, y, width, height);
+            assert!(covers(rect, matrix.transform_point(8.25, 0.5)));
+            assert!(covers(rect, matrix.transform_point(7.5, -0.25)));
+        }
+    }
+
+    fn uci(board: &Board, turn: Option<Color>, uci: &str) -> Option<Move> {
+        uci_to_move(board, turn, &uci.parse().expect("valid uci"))
+    }
+
+    #[test]
+    fn test_uci_to_move() {
+        let board: Board = "4k3/P7/8/3pP3/8/8/4P3/R3K2R".parse().expect("valid board fen");
+        let white = Some(Color::White);
+
+        assert!(uci(&board, white, "e2e4").is_some());
+        assert!(uci(&board, white, "e5d6").is_some());
+        assert!(uci(&board, white, "e1g1").is_some());
+        assert!(uci(&board, white, "e1a1").is_some());
+        assert!(uci(&board, white, "a1a5").is_some());
+        assert!(matches!(uci(&board, white, "a7a8q"),
+                         Some(Move::Normal { role: Role::Pawn, promotion: Some(Role::Queen), .. })));
+
+        // illegal promotions
+        assert!(uci(&board, white, "e2e4q").is_none());
+        assert!(uci(&board, white, "a7a8").is_none());
+        assert!(uci(&board, white, "a7a8p").is_none());
+        assert!(uci(&board, white, "a1a8q").is_none());
+
+        // pieces of the other side, or moves they cannot make
+        assert!(uci(&board, white, "e8d8").is_none());
+        assert!(uci(&board, None, "e8d8").is_some());
+        assert!(uci(&board, white, "e1e3").is_none());
+        assert!(uci(&board, white, "e2e1").is_none());
+        assert!(uci(&board, white, "a1h1").is_none());
+        assert!(uci(&board, white, "e2d3").is_none());
+    }
+
+    #[test]
+    fn test_eval_bar_fits() {
+        for &coordinate_mode in &[CoordinateMode::Always, CoordinateMode::InsideSquares, CoordinateMode::Hidden] {
+            let mut board_state = BoardState::new();
+            board_state.set_coordinate_mode(coordinate_mode);
+            board_state.set_show_eval_bar(true);
+
+            // limited by the width, so that the edges of the widget are
+            // close to the eval bar and the board
+            let matrix = compute_matrix(&board_state, 0, 0, 500, 1000);
+            let inverse = matrix.try_invert().expect("invertible");
+            let (left, _) = inverse.transform_point(0.0, 500.0);
+            let (right, _) = inverse.transform_point(500.0, 500.0);
+            assert!(left <= EVAL_BAR_LEFT + 1e-9 && left > EVAL_BAR_LEFT - 0.1);
+            assert!(right >= 8.0 - 1e-9 && right < 8.6);
+        }
+    }
+
+    #[test]
+    fn test_fixed_size() {
+        let mut board_state = BoardState::new();
+        assert_eq!(fixed_size(&board_state), None);
+
+        board_state.set_fixed_square_px(Some(64));
+        assert_eq!(fixed_size(&board_state), Some((9 * 64, 9 * 64)));
+
+        board_state.set_coordinate_mode(CoordinateMode::Hidden);
+        assert_eq!(fixed_size(&board_state), Some((8 * 64, 8 * 64)));
+
+        // room for the eval bar next to the board
+        board_state.set_show_eval_bar(true);
+        let (width, height) = fixed_size(&board_state).expect("fixed size");
+        assert_eq!(height, 8 * 64);
+        assert!(f64::from(width) >= 64.0 * (8.0 - EVAL_BAR_LEFT));
+
+        // the widget of that size shows the eval bar and the whole board
+        let inverse = compute_matrix(&board_state, 0, 0, width, height).try_invert().expect("invertible");
+        assert!(inverse.transform_point(0.0, 0.0).0 <= EVAL_BAR_LEFT + 1e-9);
+        assert!(inverse.transform_point(f64::from(width), 0.0).0 >= 8.0 - 1e-9);
+    }
+
+    #[test]
+    fn test_upright() {
+        let surface = ImageSurface::create(Format::ARgb32, 1, 1).expect("image surface");
+        let cr = Context::new(&surface).expect("context");
+
+        for &orientation in &[Color::White, Color::Black] {
+            for &mirror_files in &[false, true] {
+                for &tilt in &[0.0, 10.0] {
+                    let mut board_state = BoardState::new();
+                    board_state.set_orientation(orientation);
+                    board_state.set_mirror_files(mirror_files);
+                    board_state.set_tilt(tilt);
+
+                    cr.set_matrix(compute_matrix(&board_state, 0, 0, 900, 900));
+                    board_state.upright(&cr);
+                    let matrix = cr.matrix();
+                    assert!(matrix.xx > 0.0 && matrix.yy > 0.0);
+                    assert!(matrix.xy.abs() < 1e-9 && matrix.yx.abs() < 1e-9);
+                }
+            }
+        }
+    }
+}